@@ -7,24 +7,250 @@ pub enum ReadError<F: std::str::FromStr> {
     Parse(F::Err),
 }
 
+impl<F: std::str::FromStr> ReadError<F> {
+    /// Return the kind of the underlying io error, or None if the attribute was read but could
+    /// not be parsed.
+    pub fn io_kind(&self) -> Option<std::io::ErrorKind> {
+        match self {
+            ReadError::Io(err) => Some(err.kind()),
+            ReadError::Parse(_) => None,
+        }
+    }
+}
+
+impl<F: std::str::FromStr> std::fmt::Display for ReadError<F>
+where
+    F::Err: std::fmt::Display
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReadError::Io(err) => write!(f, "{}", err),
+            ReadError::Parse(err) => write!(f, "invalid attribute value: {}", err),
+        }
+    }
+}
+
+impl<F: std::str::FromStr + std::fmt::Debug> std::error::Error for ReadError<F>
+where
+    F::Err: std::error::Error + 'static
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ReadError::Io(err) => Some(err),
+            ReadError::Parse(err) => Some(err),
+        }
+    }
+}
+
+impl<F: std::str::FromStr> From<ReadError<F>> for std::io::Error
+where
+    F::Err: std::fmt::Display
+{
+    fn from(err: ReadError<F>) -> Self {
+        match err {
+            ReadError::Io(err) => err,
+            ReadError::Parse(err) => std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string()),
+        }
+    }
+}
+
 fn read<P: AsRef<Path>, F: std::str::FromStr>(path: P) -> std::result::Result<F, ReadError<F>> {
     let str = std::fs::read_to_string(path).map_err(|e| ReadError::Io(e))?;
     let str = str.trim();
     str.parse::<F>().map_err(|e| ReadError::Parse(e))
 }
 
+/// Combine the errors of a multi-step operation that continues past failures into one error.
+fn aggregate(what: &str, errors: Vec<std::io::Error>) -> std::io::Result<()> {
+    match errors.len() {
+        0 => Ok(()),
+        1 => Err(errors.into_iter().next().unwrap()),
+        n => {
+            let messages: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+            Err(std::io::Error::other(format!("{} failed {} times: {}", what, n, messages.join("; "))))
+        }
+    }
+}
+
+fn write<P: AsRef<Path>>(path: P, value: &str) -> std::io::Result<()> {
+    let mut file = std::fs::File::create(path)?;
+    let value_string = value.to_string() + "\n";
+    file.write_all(value_string.as_bytes())?;
+    Ok(())
+}
+
+/// Return `path` if it exists, or an `Unsupported` error if the running kernel does not provide
+/// the attribute. Return a `NotFound` error if the object the attribute belongs to does not
+/// exist, so that a missing object is not mistaken for a kernel limitation.
+fn probe(path: std::path::PathBuf) -> std::io::Result<std::path::PathBuf> {
+    if let Some(parent) = path.parent() {
+        if !parent.try_exists()? {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{} does not exist", parent.display())
+            ));
+        }
+    }
+    if !path.try_exists()? {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("{} is not supported by this kernel", path.display())
+        ));
+    }
+    Ok(path)
+}
+
+/// Return the path of the attribute `name` in `dir`. Names that could refer to a file outside
+/// of `dir` are rejected.
+fn attr_path(dir: &Path, name: &str) -> std::io::Result<std::path::PathBuf> {
+    if name.is_empty() || name.contains('/') || name.contains("..") {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("invalid attribute name {:?}", name)
+        ));
+    }
+    Ok(dir.join(name))
+}
+
+fn get_attr(dir: &Path, name: &str) -> std::io::Result<String> {
+    let value = std::fs::read_to_string(attr_path(dir, name)?)?;
+    Ok(value.trim_end_matches('\n').to_string())
+}
+
+/// Read the attribute `name` in `dir`, returning None if `dir` exists but the attribute does not.
+fn read_opt_attr(dir: &Path, name: &str) -> std::io::Result<Option<String>> {
+    match get_attr(dir, name) {
+        Ok(value) => Ok(Some(value)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound && dir.try_exists()? => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn set_attr(dir: &Path, name: &str, value: &str) -> std::io::Result<()> {
+    write(attr_path(dir, name)?, value)
+}
+
+/// Parse `hex` as a sequence of 16 bytes written as 32 hex digits.
+fn parse_hex_id(hex: &str) -> Option<[u8; 16]> {
+    if hex.len() != 32 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut bytes = [0u8; 16];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+/// Parse a UUID in the hyphenated `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx` form.
+fn parse_uuid(value: &str) -> Option<[u8; 16]> {
+    let groups: Vec<&str> = value.split('-').collect();
+    let lens: Vec<usize> = groups.iter().map(|group| group.len()).collect();
+    if lens != [8, 4, 4, 4, 12] {
+        return None;
+    }
+    parse_hex_id(&groups.concat())
+}
+
+/// Parse an NGUID written as 32 hex digits, optionally separated by hyphens as the kernel prints
+/// it.
+fn parse_nguid(value: &str) -> Option<[u8; 16]> {
+    parse_hex_id(&value.replace('-', ""))
+}
+
+/// Return a random UUID generated by the kernel.
+fn generate_uuid() -> std::io::Result<String> {
+    read::<_, String>("/proc/sys/kernel/random/uuid").map_err(std::io::Error::from)
+}
+
+/// Return the size in bytes of the block device or file at `path`.
+fn device_size<P: AsRef<Path>>(path: P) -> std::io::Result<u64> {
+    use std::io::Seek;
+    let mut file = std::fs::File::open(path)?;
+    file.seek(std::io::SeekFrom::End(0))
+}
+
+/// The auxiliary vector entry type holding the page size.
+const AT_PAGESZ: usize = 6;
+
+/// Return the page size of the running kernel, read from the auxiliary vector of this process.
+fn page_size() -> std::io::Result<u32> {
+    const WORD: usize = std::mem::size_of::<usize>();
+    let auxv = std::fs::read("/proc/self/auxv")?;
+    for entry in auxv.chunks_exact(2 * WORD) {
+        let (key, value) = entry.split_at(WORD);
+        if usize::from_ne_bytes(key.try_into().unwrap()) == AT_PAGESZ {
+            let value = usize::from_ne_bytes(value.try_into().unwrap());
+            return u32::try_from(value).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e));
+        }
+    }
+    Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "/proc/self/auxv has no AT_PAGESZ entry"))
+}
+
+/// The error returned by [`Namespace::try_enable`] describing the most likely reason the kernel
+/// refused to enable a namespace.
+#[derive(Debug)]
+pub enum EnableError {
+    /// The namespace has no device_path.
+    NoDevicePath,
+    /// The device_path does not exist.
+    DeviceNotFound(String),
+    /// The device at device_path has a size of zero.
+    EmptyDevice(String),
+    /// No precondition was violated. This is the error returned by the kernel.
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for EnableError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EnableError::NoDevicePath => write!(f, "the namespace has no device_path"),
+            EnableError::DeviceNotFound(path) => write!(f, "the device_path {} does not exist", path),
+            EnableError::EmptyDevice(path) => write!(f, "the device {} has a size of zero", path),
+            EnableError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for EnableError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EnableError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for EnableError {
+    fn from(err: std::io::Error) -> Self {
+        EnableError::Io(err)
+    }
+}
+
 #[derive(Clone)]
 pub struct Namespace {
     path: std::path::PathBuf
 }
 
+/// Formats an attribute for Debug output, printing a placeholder if it could not be read.
+struct DebugAttr<T>(std::io::Result<T>);
+
+impl<T: std::fmt::Debug> std::fmt::Debug for DebugAttr<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.0 {
+            Ok(value) => value.fmt(f),
+            Err(err) => write!(f, "<error: {}>", err),
+        }
+    }
+}
+
 impl std::fmt::Debug for Namespace {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Namespace")
-        .field("device_path", &self.device_path().map_err(|_| std::fmt::Error)?)
-        .field("device_uuid", &self.device_uuid().map_err(|_| std::fmt::Error)?)
-        .field("device_nguid", &self.device_nguid().map_err(|_| std::fmt::Error)?)
-        .field("enable", &self.enable().map_err(|_| std::fmt::Error)?)
+        .field("device_path", &DebugAttr(self.device_path()))
+        .field("device_uuid", &DebugAttr(self.device_uuid().map_err(std::io::Error::from)))
+        .field("device_nguid", &DebugAttr(self.device_nguid()))
+        .field("enable", &DebugAttr(self.enable().map_err(std::io::Error::from)))
         .finish()
     }
 }
@@ -35,6 +261,29 @@ impl Namespace {
         &self.path
     }
 
+    /// Return the namespace id parsed from the namespace path, or None if the directory name is
+    /// not a valid nsid.
+    pub fn nsid(&self) -> Option<u32> {
+        self.path.file_name()?.to_str()?.parse::<u32>().ok()
+    }
+
+    /// Read the raw value of the attribute `name` in the Namespace directory, without the trailing
+    /// newline.
+    pub fn get_attr(&self, name: &str) -> std::io::Result<String> {
+        get_attr(self.path(), name)
+    }
+
+    /// Read the raw value of the attribute `name` in the Namespace directory, returning None if
+    /// the attribute does not exist and an empty string if it is set to an empty value.
+    pub fn read_opt_attr(&self, name: &str) -> std::io::Result<Option<String>> {
+        read_opt_attr(self.path(), name)
+    }
+
+    /// Write the raw value of the attribute `name` in the Namespace directory.
+    pub fn set_attr(&mut self, name: &str, value: &str) -> std::io::Result<()> {
+        set_attr(self.path(), name, value)
+    }
+
     pub fn set_enable(&mut self, value: bool) -> std::io::Result<()> {
         let enable_path = self.path().join("enable");
         let mut file = std::fs::File::create(enable_path)?;
@@ -50,6 +299,192 @@ impl Namespace {
         read(self.path().join("enable")).map(|v| v == 1)
     }
 
+    /// Enable the namespace. If the kernel refuses, the namespace preconditions are checked to
+    /// explain the failure, falling back to the kernel error if none of them is violated.
+    pub fn try_enable(&mut self) -> std::result::Result<(), EnableError> {
+        let err = match self.set_enable(true) {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+        match self.device_path() {
+            Ok(None) => Err(EnableError::NoDevicePath),
+            Ok(Some(path)) if matches!(std::path::Path::new(&path).try_exists(), Ok(false)) => {
+                Err(EnableError::DeviceNotFound(path))
+            }
+            Ok(Some(path)) if matches!(device_size(&path), Ok(0)) => Err(EnableError::EmptyDevice(path)),
+            _ => Err(EnableError::Io(err)),
+        }
+    }
+
+    /// Set the enable state only if it differs from the current state. Return whether the
+    /// enable attribute was written.
+    pub fn set_enable_if_changed(&mut self, value: bool) -> std::io::Result<bool> {
+        if self.enable()? == value {
+            return Ok(false);
+        }
+        self.set_enable(value)?;
+        Ok(true)
+    }
+
+    /// Disable the namespace if it is enabled, apply `f`, and then restore the previous enable
+    /// state. The enable state is restored even if `f` fails, in which case the error from `f`
+    /// is returned.
+    pub fn modify_disabled<F>(&mut self, f: F) -> std::io::Result<()>
+    where
+        F: FnOnce(&mut Self) -> std::io::Result<()>
+    {
+        let enabled = self.enable()?;
+        if enabled {
+            self.set_enable(false)?;
+        }
+        let res = f(self);
+        if enabled {
+            let restored = self.set_enable(true);
+            res?;
+            return restored;
+        }
+        res
+    }
+
+    /// Write the attributes of `attrs` that differ from the current ones, disabling the namespace
+    /// while they are written and restoring the previous enable state afterwards, even if a
+    /// write fails. Nothing is written, and the namespace is not disabled, if nothing differs.
+    pub fn reconfigure(&mut self, attrs: NamespaceDesired) -> std::io::Result<()> {
+        let current_path = self.device_path()?;
+        let device_path = attrs.device_path.filter(|path| current_path.as_ref() != Some(path));
+        let current_uuid = self.device_uuid()?;
+        let device_uuid = attrs.device_uuid.filter(|uuid| *uuid != current_uuid);
+        let current_nguid = self.device_nguid()?;
+        let device_nguid = attrs.device_nguid.filter(|nguid| current_nguid.as_ref() != Some(nguid));
+        let current_grpid = self.ana_grpid()?;
+        let ana_grpid = attrs.ana_grpid.filter(|grpid| *grpid != current_grpid);
+        if device_path.is_none() && device_uuid.is_none() && device_nguid.is_none() && ana_grpid.is_none() {
+            return Ok(());
+        }
+        self.modify_disabled(|ns| {
+            if let Some(device_path) = &device_path {
+                ns.set_device_path(device_path)?;
+            }
+            if let Some(device_uuid) = &device_uuid {
+                ns.set_device_uuid(device_uuid)?;
+            }
+            if let Some(device_nguid) = &device_nguid {
+                ns.set_device_nguid(device_nguid)?;
+            }
+            if let Some(ana_grpid) = ana_grpid {
+                ns.set_ana_grpid(ana_grpid)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Point the namespace at the device `new_path`, preserving device_uuid and device_nguid.
+    /// The new device must exist and be at least as large as the current one; this is checked
+    /// before the namespace is disabled. If the swap or re-enable fails, the previous device_path
+    /// is written back and the namespace is re-enabled if it was enabled before.
+    pub fn swap_device(&mut self, new_path: &str) -> std::io::Result<()> {
+        let new_size = device_size(new_path)?;
+        let old_path = self.device_path()?;
+        if let Some(old_size) = old_path.as_ref().and_then(|path| device_size(path).ok()) {
+            if new_size < old_size {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("{} is {} bytes, smaller than the current device's {} bytes", new_path, new_size, old_size)
+                ));
+            }
+        }
+        let uuid = self.device_uuid()?;
+        let nguid = self.device_nguid()?;
+        let enabled = self.enable()?;
+        if enabled {
+            self.set_enable(false)?;
+        }
+        let res = self.set_device_path(new_path).and_then(|_| {
+            if self.device_uuid()? != uuid {
+                self.set_device_uuid(&uuid)?;
+            }
+            if let Some(nguid) = &nguid {
+                if self.device_nguid()?.as_ref() != Some(nguid) {
+                    self.set_device_nguid(nguid)?;
+                }
+            }
+            if enabled {
+                self.set_enable(true)?;
+            }
+            Ok(())
+        });
+        if res.is_err() {
+            if let Some(old_path) = &old_path {
+                let _ = self.set_device_path(old_path);
+            }
+            if enabled {
+                let _ = self.set_enable(true);
+            }
+        }
+        res
+    }
+
+    /// Move this namespace to `new_nsid` in the same subsystem, keeping device_path, device_uuid,
+    /// device_nguid, and ana_grpid so hosts see the same device at the new nsid. The original is
+    /// disabled before the new namespace is enabled, so the device is never exported twice.
+    /// Return an `AlreadyExists` error if `new_nsid` is in use. If the move fails, the new
+    /// namespace is removed and the original is re-enabled if it was enabled before.
+    pub fn move_to_nsid(mut self, new_nsid: u32) -> std::io::Result<Namespace> {
+        let subsys = self.subsystem()?;
+        let enabled = self.enable()?;
+        let template = NamespaceTemplate {
+            device_path: self.device_path()?,
+            device_uuid: Some(self.device_uuid()?),
+            device_nguid: self.device_nguid()?,
+            ana_grpid: Some(self.ana_grpid()?),
+            ..NamespaceTemplate::default()
+        };
+        let mut ns = subsys.create_namespace_from_template(new_nsid, &template)?;
+        let mut res = Ok(());
+        if enabled {
+            res = self.set_enable(false).and_then(|_| ns.set_enable(true));
+        }
+        if let Err(err) = res.and_then(|_| std::fs::remove_dir(self.path())) {
+            let _ = ns.set_enable(false);
+            let _ = std::fs::remove_dir(ns.path());
+            if enabled {
+                let _ = self.set_enable(true);
+            }
+            return Err(err);
+        }
+        Ok(ns)
+    }
+
+    /// Poll the enable state every `interval` and call `f` with the new state whenever it
+    /// changes. Polling stops when `f` returns false or the enable state cannot be read.
+    pub fn poll_enable(&self, interval: std::time::Duration, mut f: impl FnMut(bool) -> bool) -> std::io::Result<()> {
+        let mut current = self.enable()?;
+        loop {
+            std::thread::sleep(interval);
+            let enable = self.enable()?;
+            if enable != current {
+                current = enable;
+                if !f(enable) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Set whether the namespace uses buffered I/O through the page cache. This can only be
+    /// changed while the namespace is disabled.
+    pub fn set_buffered_io(&mut self, value: bool) -> std::io::Result<()> {
+        write(self.path().join("buffered_io"), if value { "1" } else { "0" })
+    }
+
+    /// Return whether the namespace uses buffered I/O through the page cache. Unlike setting it,
+    /// reading the attribute is allowed while the namespace is enabled, in which case this is the
+    /// value in effect. Only file-backed namespaces go through the page cache.
+    pub fn effective_buffered_io(&self) -> std::io::Result<bool> {
+        let value: u8 = read(self.path().join("buffered_io"))?;
+        Ok(value == 1)
+    }
+
     pub fn set_ana_grpid(&mut self, value: u32) -> std::io::Result<()> {
         let attr_path = self.path().join("ana_grpid");
         let mut file = std::fs::File::create(attr_path)?;
@@ -60,8 +495,9 @@ impl Namespace {
 
     pub fn ana_grpid(&self) -> std::io::Result<u32> {
         let ana_grpid_path = self.path().join("ana_grpid");
-        let ana_grpid_str = std::fs::read_to_string(ana_grpid_path).unwrap();
-        let ana_grpid = ana_grpid_str.trim_end_matches('\n').parse::<u32>().unwrap();
+        let ana_grpid_str = std::fs::read_to_string(ana_grpid_path)?;
+        let ana_grpid = ana_grpid_str.trim_end_matches('\n').parse::<u32>()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
         Ok(ana_grpid)
     }
 
@@ -73,8 +509,17 @@ impl Namespace {
         Ok(())
     }
 
-    pub fn device_nguid(&self)-> std::result::Result<String, ReadError<String>> {
-        read(self.path().join("device_nguid"))
+    /// Set device_nguid to the NGUID derived from this namespace's device_uuid by
+    /// [`nguid_from_uuid`], so that the identifiers stay consistent when the namespace is
+    /// recreated with the same uuid.
+    pub fn set_device_nguid_from_uuid(&mut self) -> std::io::Result<()> {
+        let nguid = nguid_from_uuid(&self.device_uuid()?)?;
+        self.set_device_nguid(&nguid)
+    }
+
+    /// Return device_nguid, or None on kernels without the attribute.
+    pub fn device_nguid(&self) -> std::io::Result<Option<String>> {
+        self.read_opt_attr("device_nguid")
     }
 
     pub fn set_device_uuid(&mut self, value: &str) -> std::io::Result<()> {
@@ -89,6 +534,25 @@ impl Namespace {
         read(self.path().join("device_uuid"))
     }
 
+    /// Check that device_uuid is a well-formed UUID and device_nguid is a well-formed 16 byte
+    /// NGUID. All-zero identifiers are rejected since the NVMe specification reserves them to
+    /// mean that no identifier is reported. device_nguid is not checked on kernels without it.
+    pub fn validate_identifiers(&self) -> std::io::Result<()> {
+        let invalid = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+        let uuid = self.device_uuid()?;
+        match parse_uuid(&uuid) {
+            None => return Err(invalid(format!("device_uuid {:?} is not a valid UUID", uuid))),
+            Some(bytes) if bytes == [0; 16] => return Err(invalid("device_uuid is all zero".to_string())),
+            Some(_) => {}
+        }
+        let Some(nguid) = self.device_nguid()? else { return Ok(()) };
+        match parse_nguid(&nguid) {
+            None => Err(invalid(format!("device_nguid {:?} is not 32 hex digits", nguid))),
+            Some(bytes) if bytes == [0; 16] => Err(invalid("device_nguid is all zero".to_string())),
+            Some(_) => Ok(()),
+        }
+    }
+
     pub fn set_device_path(&mut self, value: &str) -> std::io::Result<()> {
         let path = self.path().join("device_path");
         let mut file = std::fs::File::create(path)?;
@@ -99,57 +563,443 @@ impl Namespace {
 
     pub fn device_path(&self) -> std::io::Result<Option<String>> {
         let path = self.path().join("device_path");
-        match std::fs::read_to_string(path).unwrap().as_str() {
+        match std::fs::read_to_string(path)?.as_str() {
             "(null)\n" => Ok(None),
             str =>  Ok(Some(str.trim_end_matches('\n').to_string()))
         }
        
     }
 
+    /// Return the device_path as a PathBuf, or None if no device is set.
+    pub fn device_path_buf(&self) -> std::io::Result<Option<std::path::PathBuf>> {
+        Ok(self.device_path()?.map(std::path::PathBuf::from))
+    }
+
+    /// Set the PCI device used for peer-to-peer memory. This can only be changed while the
+    /// namespace is disabled.
+    pub fn set_p2pmem(&mut self, value: &str) -> std::io::Result<()> {
+        write(probe(self.path().join("p2pmem"))?, value)
+    }
+
+    pub fn p2pmem(&self) -> std::result::Result<String, ReadError<String>> {
+        read(probe(self.path().join("p2pmem")).map_err(ReadError::Io)?)
+    }
+
+    /// Set the logical block size in bytes advertised for this namespace. The size must be a
+    /// power of two of at least 512. This is only available on kernels that expose a `blksize`
+    /// namespace attribute, and can only be changed while the namespace is disabled.
+    pub fn set_blksize(&mut self, value: u32) -> std::io::Result<()> {
+        if value < 512 || !value.is_power_of_two() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("blksize {} is not a power of two of at least 512", value)
+            ));
+        }
+        write(probe(self.path().join("blksize"))?, &value.to_string())
+    }
+
+    pub fn blksize(&self) -> std::result::Result<u32, ReadError<u32>> {
+        read(probe(self.path().join("blksize")).map_err(ReadError::Io)?)
+    }
+
+    /// Export this namespace as read-only. This is only available on kernels that expose a
+    /// `read_only` namespace attribute.
+    pub fn set_read_only(&mut self, value: bool) -> std::io::Result<()> {
+        write(probe(self.path().join("read_only"))?, if value { "1" } else { "0" })
+    }
+
+    pub fn is_read_only(&self) -> std::result::Result<bool, ReadError<u8>> {
+        read(probe(self.path().join("read_only")).map_err(ReadError::Io)?).map(|v: u8| v == 1)
+    }
+
+    /// Set whether this namespace may be attached to more than one controller. This is only
+    /// available on kernels that expose a `shared` namespace attribute.
+    pub fn set_shared(&mut self, value: bool) -> std::io::Result<()> {
+        write(probe(self.path().join("shared"))?, if value { "1" } else { "0" })
+    }
+
+    /// Return whether this namespace may be attached to more than one controller. Kernels
+    /// without a `shared` attribute report every namespace as shared, so this returns true there.
+    /// Return a `NotFound` error if the namespace does not exist.
+    pub fn is_shared(&self) -> std::result::Result<bool, ReadError<u8>> {
+        match probe(self.path().join("shared")) {
+            Ok(path) => read(path).map(|v: u8| v == 1),
+            Err(err) if err.kind() == std::io::ErrorKind::Unsupported => Ok(true),
+            Err(err) => Err(ReadError::Io(err)),
+        }
+    }
+
+    /// Return the I/O statistics of this namespace. The `stat` attribute uses the same layout as
+    /// the block layer `stat` file, and is not provided by every kernel.
+    pub fn io_stats(&self) -> std::io::Result<IoStats> {
+        let stat = std::fs::read_to_string(probe(self.path().join("stat"))?)?;
+        let fields = stat.split_whitespace()
+            .map(|field| field.parse::<u64>())
+            .collect::<std::result::Result<Vec<u64>, _>>()
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+        if fields.len() < 7 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("expected at least 7 fields in stat, found {}", fields.len())
+            ));
+        }
+        let bytes = |sectors: u64| sectors.checked_mul(512).ok_or_else(|| std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{} sectors in stat overflow a byte count", sectors)
+        ));
+        Ok(IoStats {
+            read_ios: fields[0],
+            read_bytes: bytes(fields[2])?,
+            write_ios: fields[4],
+            write_bytes: bytes(fields[6])?,
+        })
+    }
+
+    /// Return the subsystem that owns this namespace, derived from the namespace path
+    /// `subsystems/<nqn>/namespaces/<nsid>`. Return an error if the path does not have that
+    /// layout.
+    pub fn subsystem(&self) -> std::io::Result<Subsystem> {
+        let namespaces = self.path.parent().filter(|dir| dir.file_name() == Some("namespaces".as_ref()));
+        let subsys = namespaces.and_then(|dir| dir.parent());
+        let subsystems = subsys.and_then(|dir| dir.parent()).filter(|dir| dir.file_name() == Some("subsystems".as_ref()));
+        match (subsys.and_then(|dir| dir.file_name()), subsystems) {
+            (Some(nqn), Some(_)) => Ok(Subsystem::from_nqn(nqn.to_os_string())),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} is not a namespace path", self.path.display())
+            )),
+        }
+    }
+
+    /// Return the ANA state of this namespace on each port that exports the owning subsystem.
+    /// Ports that do not have the namespace's ANA group are omitted.
+    pub fn ana_states(&self) -> std::io::Result<Vec<(Port, AnaState)>> {
+        let grpid = self.ana_grpid()?;
+        let subsys = self.subsystem()?;
+        let mut states = Vec::new();
+        for port in Port::list_all()? {
+            if !port.has_subsystem(&subsys)? {
+                continue;
+            }
+            match port.ana_group(grpid).state() {
+                Ok(state) => states.push((port, state)),
+                Err(ReadError::Io(err)) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(states)
+    }
+
+}
+
+impl AsRef<Path> for Namespace {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// The attributes applied to a new namespace by [`Subsystem::create_namespace_from_template`].
+/// Attributes that are None are left at the kernel default.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NamespaceTemplate {
+    pub device_path: Option<String>,
+    pub device_uuid: Option<String>,
+    pub device_nguid: Option<String>,
+    pub ana_grpid: Option<u32>,
+    pub buffered_io: Option<bool>,
+    /// Whether to enable the namespace after the other attributes are applied.
+    pub enable: bool,
+}
+
+impl NamespaceTemplate {
+    fn apply(&self, ns: &mut Namespace) -> std::io::Result<()> {
+        if let Some(device_path) = &self.device_path {
+            ns.set_device_path(device_path)?;
+        }
+        if let Some(device_uuid) = &self.device_uuid {
+            ns.set_device_uuid(device_uuid)?;
+        }
+        if let Some(device_nguid) = &self.device_nguid {
+            ns.set_device_nguid(device_nguid)?;
+        }
+        if let Some(ana_grpid) = self.ana_grpid {
+            ns.set_ana_grpid(ana_grpid)?;
+        }
+        if let Some(buffered_io) = self.buffered_io {
+            ns.set_buffered_io(buffered_io)?;
+        }
+        if self.enable {
+            ns.set_enable(true)?;
+        }
+        Ok(())
+    }
+}
+
+/// The desired attributes of a namespace for [`Namespace::reconfigure`]. Attributes that are
+/// None are left unchanged.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NamespaceDesired {
+    pub device_path: Option<String>,
+    pub device_uuid: Option<String>,
+    pub device_nguid: Option<String>,
+    pub ana_grpid: Option<u32>,
+}
+
+/// The I/O statistics of a namespace returned by [`Namespace::io_stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IoStats {
+    pub read_ios: u64,
+    pub read_bytes: u64,
+    pub write_ios: u64,
+    pub write_bytes: u64,
+}
+
+/// How a subsystem can be reached by hosts, as returned by [`Subsystem::accessibility`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Accessibility {
+    /// The subsystem is not linked to any port, so no host can reach it.
+    NoPorts,
+    /// The subsystem is linked to a port and allows any host.
+    Open,
+    /// The subsystem is linked to a port and allows the given number of hosts.
+    RestrictedToHosts(usize),
+    /// The subsystem is linked to a port but neither allows any host nor has allowed hosts, so
+    /// no host can connect.
+    NoHosts,
+}
+
+/// The identify controller fields a host sees for a subsystem, as returned by
+/// [`Subsystem::identify_controller_fields`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IdentifyFields {
+    pub model: String,
+    pub serial: String,
+    pub firmware: String,
+    pub version: String,
+}
+
+/// The controller identity attributes of a subsystem, as used by [`Subsystem::identity`] and
+/// [`Subsystem::set_identity`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ControllerIdentity {
+    pub model: Option<String>,
+    pub serial: Option<String>,
+    pub firmware: Option<String>,
+    pub ieee_oui: Option<String>,
+    pub version: Option<String>,
+}
+
+/// The controller sizing attributes of a subsystem, as used by [`Subsystem::limits`] and
+/// [`Subsystem::set_limits`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ControllerLimits {
+    pub cntlid_min: u16,
+    pub cntlid_max: u16,
+    /// The maximum I/O queue id, or None on kernels without `attr_qid_max`.
+    pub qid_max: Option<u16>,
+}
+
+/// The result of [`Subsystem::set_allow_any_host_with_warning`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ToggleOutcome {
+    /// The value of attr_allow_any_host before it was set.
+    pub previous: bool,
+    /// The names of the controllers connected to the subsystem when it was set.
+    pub connected_controllers: Vec<String>,
+}
+
+/// The optional NVM commands a subsystem advertises in the ONCS field of its identify
+/// controller data, as returned by [`Subsystem::attr_oncs`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CommandSupport(u16);
+
+impl CommandSupport {
+    pub const COMPARE: CommandSupport = CommandSupport(1 << 0);
+    pub const WRITE_UNCORRECTABLE: CommandSupport = CommandSupport(1 << 1);
+    pub const DATASET_MANAGEMENT: CommandSupport = CommandSupport(1 << 2);
+    pub const WRITE_ZEROES: CommandSupport = CommandSupport(1 << 3);
+    pub const SAVE_SELECT: CommandSupport = CommandSupport(1 << 4);
+    pub const RESERVATIONS: CommandSupport = CommandSupport(1 << 5);
+    pub const TIMESTAMP: CommandSupport = CommandSupport(1 << 6);
+    pub const VERIFY: CommandSupport = CommandSupport(1 << 7);
+    pub const COPY: CommandSupport = CommandSupport(1 << 8);
+
+    /// Return the flags for the raw ONCS value, keeping bits not known to this crate.
+    pub fn from_bits(bits: u16) -> CommandSupport {
+        CommandSupport(bits)
+    }
+
+    /// Return the raw ONCS value.
+    pub fn bits(&self) -> u16 {
+        self.0
+    }
+
+    /// Return whether every flag set in `other` is also set in `self`.
+    pub fn contains(&self, other: CommandSupport) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for CommandSupport {
+    type Output = CommandSupport;
+
+    fn bitor(self, rhs: CommandSupport) -> CommandSupport {
+        CommandSupport(self.0 | rhs.0)
+    }
+}
+
+impl std::fmt::Display for CommandSupport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#06x}", self.0)
+    }
+}
+
+impl std::str::FromStr for CommandSupport {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let bits = match s.strip_prefix("0x") {
+            Some(hex) => u16::from_str_radix(hex, 16)?,
+            None => s.parse()?,
+        };
+        Ok(CommandSupport(bits))
+    }
 }
 
 pub struct Subsystem {
-    nqn: std::ffi::OsString
+    nqn: std::ffi::OsString,
+    path: std::path::PathBuf
 }
 
 const CONFIGFS_DIR: &str = "/sys/kernel/config/nvmet/";
 
+/// The well-known nqn of the discovery subsystem.
+pub const DISCOVERY_NQN: &str = "nqn.2014-08.org.nvmexpress.discovery";
+
+const SYSFS_MODULE_DIR: &str = "/sys/module/";
+
+const SYSFS_NVME_DIR: &str = "/sys/class/nvme/";
+
+/// The smallest controller id that is not reserved by the NVMe specification.
+const CNTLID_MIN: u16 = 0x0001;
+/// The largest controller id that is not reserved by the NVMe specification.
+const CNTLID_MAX: u16 = 0xFFEF;
+/// The largest attr_qid_max the kernel accepts.
+const QID_MAX: u16 = 128;
+
+/// Return an error if `value` does not fit in an identify controller field of `max` bytes.
+fn check_field_len(name: &str, value: &str, max: usize) -> std::io::Result<()> {
+    if value.len() > max {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} {:?} is {} bytes, the NVMe field holds at most {}", name, value, value.len(), max)
+        ));
+    }
+    Ok(())
+}
+
+fn check_qid_max(value: u16) -> std::io::Result<()> {
+    if !(1..=QID_MAX).contains(&value) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("qid_max {} is out of range, the kernel allows 1..={}", value, QID_MAX)
+        ));
+    }
+    Ok(())
+}
+
+fn check_cntlid(value: u16) -> std::io::Result<()> {
+    if !(CNTLID_MIN..=CNTLID_MAX).contains(&value) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "cntlid {:#x} is reserved: NVMe reserves 0x0 and 0xfff0..=0xffff, valid controller ids are {:#x}..={:#x}",
+                value, CNTLID_MIN, CNTLID_MAX
+            )
+        ));
+    }
+    Ok(())
+}
+
 impl std::fmt::Debug for Subsystem {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Subsystem")
         .field("nqn", &self.nqn)
-        .field("attr_allow_any_host", &self.attr_allow_any_host().map_err(|_| std::fmt::Error)?)
-        .field("attr_cntlid_min", &self.attr_cntlid_min().map_err(|_| std::fmt::Error)?)
-        .field("attr_cntlid_max", &self.attr_cntlid_max().map_err(|_| std::fmt::Error)?)
-        .field("attr_model", &self.attr_model().map_err(|_| std::fmt::Error)?)
-        .field("attr_serial", &self.attr_serial().map_err(|_| std::fmt::Error)?)
-        .field("namespaces", &match self.namespaces() {
-            Ok(iter) => Ok(iter.collect::<Vec<Namespace>>()),
-            Err(_) => Err(std::fmt::Error),
-        }?)
+        .field("attr_allow_any_host", &DebugAttr(self.attr_allow_any_host()))
+        .field("attr_cntlid_min", &DebugAttr(self.attr_cntlid_min()))
+        .field("attr_cntlid_max", &DebugAttr(self.attr_cntlid_max()))
+        .field("attr_model", &DebugAttr(self.attr_model()))
+        .field("attr_serial", &DebugAttr(self.attr_serial()))
+        .field("namespaces", &DebugAttr(self.namespaces().map(|iter| iter.collect::<Vec<Namespace>>())))
         .finish()
     }
 }
 
 impl Subsystem {
+    fn from_nqn(nqn: std::ffi::OsString) -> Subsystem {
+        let path = std::path::Path::new(CONFIGFS_DIR).join("subsystems").join(&nqn);
+        Subsystem { nqn, path }
+    }
+
     /// Add a new subsystem with the given nqn. Return an error if a subsystem with the given
     /// nqn already exists.
     pub fn new<T>(nqn: T) -> std::io::Result<Subsystem> 
     where 
         std::ffi::OsString: From<T>
     {
-        let subsys = Subsystem{ nqn: std::ffi::OsString::from(nqn) };
+        let subsys = Subsystem::from_nqn(std::ffi::OsString::from(nqn));
         std::fs::create_dir(subsys.path())?;
         Ok(subsys)
     }
 
+    /// Add a new subsystem with the given nqn and link it to each of `ports`. Return an error
+    /// without creating anything if a port does not exist. If a link fails, the links made so
+    /// far and the subsystem are removed again.
+    pub fn new_on_ports<T>(nqn: T, ports: &[&Port]) -> std::io::Result<Subsystem>
+    where
+        std::ffi::OsString: From<T>
+    {
+        for port in ports {
+            if !port.path().try_exists()? {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("port {} does not exist", port.id())
+                ));
+            }
+        }
+        let subsys = Subsystem::new(nqn)?;
+        for (i, port) in ports.iter().enumerate() {
+            if let Err(err) = port.add_subsystem(&subsys) {
+                for port in &ports[..i] {
+                    let _ = port.remove_subsystem(subsys.nqn());
+                }
+                let _ = std::fs::remove_dir(subsys.path());
+                return Err(err);
+            }
+        }
+        Ok(subsys)
+    }
+
+    /// Add a new subsystem with the NVMe UUID nqn for the given uuid, as formatted by
+    /// [`uuid_nqn`]. Return an error if the uuid is malformed or the subsystem already exists.
+    pub fn new_uuid_nqn(uuid: &str) -> std::io::Result<Subsystem> {
+        Subsystem::new(uuid_nqn(uuid)?)
+    }
+
     /// Return the subsystem with the given nqn. This will not return an error if the subsystem does
     /// not exist.
     pub fn open<T>(nqn: T) -> Subsystem 
     where 
         std::ffi::OsString: From<T>
     {
-        Subsystem{ nqn: std::ffi::OsString::from(nqn) }
+        Subsystem::from_nqn(std::ffi::OsString::from(nqn))
+    }
+
+    /// Return a handle with the reserved [`DISCOVERY_NQN`]. The kernel keeps the discovery
+    /// subsystem out of configfs and refuses to create a directory for it, so the handle is only
+    /// useful for comparing nqns; its attribute and allowed_hosts methods fail with `NotFound`.
+    pub fn discovery() -> Subsystem {
+        Subsystem::from_nqn(std::ffi::OsString::from(DISCOVERY_NQN))
     }
 
     /// Return a boolean indicating whether or not a subsystem with the given nqn exists.
@@ -157,33 +1007,138 @@ impl Subsystem {
     where 
         std::ffi::OsString: From<T>
     {
-        Subsystem { nqn: std::ffi::OsString::from(nqn) }.path().try_exists()
+        Subsystem::from_nqn(std::ffi::OsString::from(nqn)).path().try_exists()
     }
 
     /// Remove the subsystem with the given nqn. This will return an error if a subsystem with the given
-    /// nqn does not exist.
+    /// nqn does not exist, or if the nqn is the reserved [`DISCOVERY_NQN`]; use
+    /// [`Subsystem::delete_unchecked`] to remove the discovery subsystem.
     pub fn delete<T>(nqn: T) -> std::io::Result<()>
     where 
         std::ffi::OsString: From<T>
     {
-        std::fs::remove_dir(Subsystem{ nqn: std::ffi::OsString::from(nqn) }.path())
+        let subsys = Subsystem::from_nqn(std::ffi::OsString::from(nqn));
+        if subsys.is_discovery() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "refusing to delete the discovery subsystem, which would break discovery for every host"
+            ));
+        }
+        std::fs::remove_dir(subsys.path())
     }
 
-    /// Return the host nqn.
-    pub fn nqn<'a>(&'a self) -> &'a str {
-        &self.nqn.to_str().unwrap()
+    /// Remove the subsystem with the given nqn, even if it is the discovery subsystem.
+    pub fn delete_unchecked<T>(nqn: T) -> std::io::Result<()>
+    where
+        std::ffi::OsString: From<T>
+    {
+        std::fs::remove_dir(Subsystem::from_nqn(std::ffi::OsString::from(nqn)).path())
+    }
+
+    /// Return the host nqn.
+    pub fn nqn(&self) -> &str {
+        self.nqn.to_str().unwrap()
+    }
+
+    /// Return whether this is the discovery subsystem, by comparing the nqn with
+    /// [`DISCOVERY_NQN`].
+    pub fn is_discovery(&self) -> bool {
+        self.nqn == DISCOVERY_NQN
     }
 
     /// Create a namespace in the given subsystem with the given nsid. Return an error
-    /// if a namespace with the given nsid already exists in the subsystem.
+    /// if a namespace with the given nsid already exists in the subsystem, or if the nsid
+    /// is reserved by the NVMe specification.
     pub fn create_namespace(&self, nsid: u32) -> std::io::Result<Namespace> {
+        if nsid == 0 || nsid == 0xFFFFFFFF {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("nsid {:#x} is reserved: NVMe nsids must be in 0x1..=0xfffffffe", nsid)
+            ));
+        }
         let path = self.path().join("namespaces").join(nsid.to_string());
         std::fs::create_dir(&path)?;
-        Ok(Namespace { path: path })
+        Ok(Namespace { path })
+    }
+
+    /// Create a namespace with the given nsid and apply the attributes of `template`, enabling
+    /// it last if the template says so. If an attribute cannot be applied, the namespace is
+    /// removed again.
+    pub fn create_namespace_from_template(&self, nsid: u32, template: &NamespaceTemplate) -> std::io::Result<Namespace> {
+        let mut ns = self.create_namespace(nsid)?;
+        let res = template.apply(&mut ns);
+        if let Err(err) = res {
+            let _ = ns.set_enable(false);
+            let _ = std::fs::remove_dir(ns.path());
+            return Err(err);
+        }
+        Ok(ns)
+    }
+
+    /// Create and enable a namespace for each device, with nsids 1, 2, ... in device order and a
+    /// freshly generated device_uuid. If any namespace cannot be set up, every namespace created
+    /// by this call is removed again.
+    pub fn create_namespaces_for_devices(&self, devices: &[&str]) -> std::io::Result<Vec<Namespace>> {
+        let mut namespaces: Vec<Namespace> = Vec::new();
+        for (nsid, device_path) in (1..).zip(devices) {
+            let template = generate_uuid().map(|device_uuid| NamespaceTemplate {
+                device_path: Some(device_path.to_string()),
+                device_uuid: Some(device_uuid),
+                enable: true,
+                ..NamespaceTemplate::default()
+            });
+            let res = template.and_then(|template| self.create_namespace_from_template(nsid, &template));
+            match res {
+                Ok(ns) => namespaces.push(ns),
+                Err(err) => {
+                    for mut ns in namespaces {
+                        let _ = ns.set_enable(false);
+                        let _ = std::fs::remove_dir(ns.path());
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        Ok(namespaces)
+    }
+
+    /// Create a namespace with the smallest unused nsid, starting at 1. If another writer
+    /// creates the chosen nsid first, the next free nsid is tried.
+    pub fn create_next_namespace(&self) -> std::io::Result<Namespace> {
+        let mut used: std::collections::HashSet<u32> = self.namespaces_map()?.into_keys().collect();
+        let mut nsid = 1;
+        loop {
+            while used.contains(&nsid) {
+                nsid += 1;
+            }
+            match self.create_namespace(nsid) {
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                    used.insert(nsid);
+                }
+                res => return res,
+            }
+        }
     }
 
     pub fn path(&self) -> std::path::PathBuf {
-        std::path::Path::new(CONFIGFS_DIR).join("subsystems").join(&self.nqn)
+        self.path.clone()
+    }
+
+    /// Read the raw value of the attribute `name` in the Subsystem directory, without the trailing
+    /// newline.
+    pub fn get_attr(&self, name: &str) -> std::io::Result<String> {
+        get_attr(&self.path(), name)
+    }
+
+    /// Read the raw value of the attribute `name` in the Subsystem directory, returning None if
+    /// the attribute does not exist and an empty string if it is set to an empty value.
+    pub fn read_opt_attr(&self, name: &str) -> std::io::Result<Option<String>> {
+        read_opt_attr(&self.path(), name)
+    }
+
+    /// Write the raw value of the attribute `name` in the Subsystem directory.
+    pub fn set_attr(&mut self, name: &str, value: &str) -> std::io::Result<()> {
+        set_attr(&self.path(), name, value)
     }
     
     pub fn set_attr_allow_any_host(&mut self, value: bool) -> std::io::Result<()> {
@@ -197,14 +1152,44 @@ impl Subsystem {
         Ok(())
     }
 
+    /// Set attr_allow_any_host, refusing to allow any host while allowed_hosts is non-empty
+    /// since that would silently make the host list ineffective. Pass `force` to skip the check.
+    pub fn set_allow_any_host_safe(&mut self, value: bool, force: bool) -> std::io::Result<()> {
+        if value && !force {
+            let count = self.allowed_hosts()?.count();
+            if count > 0 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "refusing to allow any host on {}: allowed_hosts has {} entries which would no longer restrict access",
+                        self.nqn(), count
+                    )
+                ));
+            }
+        }
+        self.set_attr_allow_any_host(value)
+    }
+
+    /// Set attr_allow_any_host and report the controllers connected when it was changed, so the
+    /// caller can tell whether revoking access may have dropped initiators. Only controllers on
+    /// this machine, as listed by [`Subsystem::live_controllers`], are seen.
+    pub fn set_allow_any_host_with_warning(&mut self, value: bool) -> std::io::Result<ToggleOutcome> {
+        let previous = self.attr_allow_any_host()?;
+        let connected_controllers = self.live_controllers()?;
+        self.set_attr_allow_any_host(value)?;
+        Ok(ToggleOutcome { previous, connected_controllers })
+    }
+
     pub fn attr_allow_any_host(&self) -> std::io::Result<bool> {
         let attr_allow_any_host_path = self.path().join("attr_allow_any_host");
-        let attr_allow_any_host_str = std::fs::read_to_string(attr_allow_any_host_path).unwrap();
+        let attr_allow_any_host_str = std::fs::read_to_string(attr_allow_any_host_path)?;
         let attr_allow_any_host = attr_allow_any_host_str == "1\n";
         Ok(attr_allow_any_host)
     }
 
+    /// Set attr_cntlid_max. Controller ids reserved by the NVMe specification are rejected.
     pub fn set_attr_cntlid_max(&mut self, value: u16) -> std::io::Result<()> {
+        check_cntlid(value)?;
         let attr_path = self.path().join("attr_cntlid_max");
         let mut file = std::fs::File::create(attr_path)?;
         let value_string =  value.to_string() + "\n";
@@ -214,12 +1199,84 @@ impl Subsystem {
 
     pub fn attr_cntlid_max(&self) -> std::io::Result<u16> {
         let attr_cntlid_max_path = self.path().join("attr_cntlid_max");
-        let attr_cntlid_max_str = std::fs::read_to_string(attr_cntlid_max_path).unwrap();
-        let attr_cntlid_max = attr_cntlid_max_str.trim_end_matches('\n').parse::<u16>().unwrap();
+        let attr_cntlid_max_str = std::fs::read_to_string(attr_cntlid_max_path)?;
+        let attr_cntlid_max = attr_cntlid_max_str.trim_end_matches('\n').parse::<u16>()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
         Ok(attr_cntlid_max)
     }
 
+    /// Return the range of controller ids this subsystem assigns.
+    pub fn cntlid_range(&self) -> std::io::Result<std::ops::RangeInclusive<u16>> {
+        Ok(self.attr_cntlid_min()?..=self.attr_cntlid_max()?)
+    }
+
+    /// Set the range of controller ids this subsystem assigns. The kernel rejects a min above
+    /// the current max and vice versa, so the bounds are written in whichever order keeps them
+    /// valid.
+    pub fn set_cntlid_range(&mut self, range: std::ops::RangeInclusive<u16>) -> std::io::Result<()> {
+        let (min, max) = range.into_inner();
+        if min > max {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("cntlid range {}..={} is empty", min, max)
+            ));
+        }
+        check_cntlid(min)?;
+        check_cntlid(max)?;
+        if min > self.attr_cntlid_max()? {
+            self.set_attr_cntlid_max(max)?;
+            self.set_attr_cntlid_min(min)
+        } else {
+            self.set_attr_cntlid_min(min)?;
+            self.set_attr_cntlid_max(max)
+        }
+    }
+
+    /// Set the maximum I/O queue id a controller of this subsystem allows, between 1 and the
+    /// kernel's limit of 128. This is only available on kernels that expose an `attr_qid_max`
+    /// subsystem attribute.
+    pub fn set_attr_qid_max(&mut self, value: u16) -> std::io::Result<()> {
+        check_qid_max(value)?;
+        write(probe(self.path().join("attr_qid_max"))?, &value.to_string())
+    }
+
+    pub fn attr_qid_max(&self) -> std::result::Result<u16, ReadError<u16>> {
+        read(probe(self.path().join("attr_qid_max")).map_err(ReadError::Io)?)
+    }
+
+    /// Return the controller id range and, where the kernel provides it, the maximum queue id
+    /// of this subsystem.
+    pub fn limits(&self) -> std::io::Result<ControllerLimits> {
+        let qid_max = match self.attr_qid_max() {
+            Ok(qid_max) => Some(qid_max),
+            Err(ReadError::Io(err)) if err.kind() == std::io::ErrorKind::Unsupported => None,
+            Err(err) => return Err(err.into()),
+        };
+        Ok(ControllerLimits {
+            cntlid_min: self.attr_cntlid_min()?,
+            cntlid_max: self.attr_cntlid_max()?,
+            qid_max,
+        })
+    }
+
+    /// Set the controller id range as [`Subsystem::set_cntlid_range`] does, and the maximum
+    /// queue id if it is Some. All values are checked, and attr_qid_max is probed, before
+    /// anything is written.
+    pub fn set_limits(&mut self, limits: &ControllerLimits) -> std::io::Result<()> {
+        if let Some(qid_max) = limits.qid_max {
+            check_qid_max(qid_max)?;
+            probe(self.path().join("attr_qid_max"))?;
+        }
+        self.set_cntlid_range(limits.cntlid_min..=limits.cntlid_max)?;
+        if let Some(qid_max) = limits.qid_max {
+            self.set_attr_qid_max(qid_max)?;
+        }
+        Ok(())
+    }
+
+    /// Set attr_cntlid_min. Controller ids reserved by the NVMe specification are rejected.
     pub fn set_attr_cntlid_min(&mut self, value: u16) -> std::io::Result<()> {
+        check_cntlid(value)?;
         let attr_path = self.path().join("attr_cntlid_min");
         let mut file = std::fs::File::create(attr_path)?;
         let value_string =  value.to_string() + "\n";
@@ -229,8 +1286,9 @@ impl Subsystem {
 
     pub fn attr_cntlid_min(&self) -> std::io::Result<u16> {
         let attr_cntlid_min_path = self.path().join("attr_cntlid_min");
-        let attr_cntlid_min_str = std::fs::read_to_string(attr_cntlid_min_path).unwrap();
-        let attr_cntlid_min = attr_cntlid_min_str.trim_end_matches('\n').parse::<u16>().unwrap();
+        let attr_cntlid_min_str = std::fs::read_to_string(attr_cntlid_min_path)?;
+        let attr_cntlid_min = attr_cntlid_min_str.trim_end_matches('\n').parse::<u16>()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
         Ok(attr_cntlid_min)
     }
 
@@ -244,7 +1302,7 @@ impl Subsystem {
 
     pub fn attr_model(&self) -> std::io::Result<String> {
         let attr_model_path = self.path().join("attr_model");
-        let attr_model_str = std::fs::read_to_string(attr_model_path).unwrap();
+        let attr_model_str = std::fs::read_to_string(attr_model_path)?;
         Ok(attr_model_str.trim_end_matches('\n').to_string())
     }
 
@@ -258,10 +1316,261 @@ impl Subsystem {
 
     pub fn attr_serial(&self) -> std::io::Result<String> {
         let attr_serial_path = self.path().join("attr_serial");
-        let attr_serial_str = std::fs::read_to_string(attr_serial_path).unwrap();
+        let attr_serial_str = std::fs::read_to_string(attr_serial_path)?;
         Ok(attr_serial_str.trim_end_matches('\n').to_string())
     }
 
+    /// Reconcile allowed_hosts with the `desired` host nqns by removing the links of hosts that
+    /// are not desired and adding links for desired hosts that are missing.
+    /// attr_allow_any_host is not changed.
+    pub fn reconcile_allowed_hosts(&self, desired: &[&str]) -> std::io::Result<()> {
+        let current: Vec<Host> = self.allowed_hosts()?.collect();
+        for host in &current {
+            if !desired.contains(&host.nqn()) {
+                self.remove_allowed_host(host.nqn())?;
+            }
+        }
+        for nqn in desired {
+            if !current.iter().any(|host| host.nqn() == *nqn) {
+                self.add_allowed_host(&Host::open(*nqn))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove every allowed host link and disable and remove every namespace of this subsystem,
+    /// continuing past failures.
+    fn clear(&self) -> std::io::Result<()> {
+        let mut errors = Vec::new();
+        for host in self.allowed_hosts()? {
+            errors.extend(self.remove_allowed_host(host.nqn()).err());
+        }
+        for mut ns in self.namespaces()? {
+            errors.extend(ns.set_enable(false).err());
+            errors.extend(std::fs::remove_dir(ns.path()).err());
+        }
+        aggregate("clearing subsystem", errors)
+    }
+
+    /// Classify how hosts can reach this subsystem from its port links, attr_allow_any_host,
+    /// and allowed_hosts.
+    pub fn accessibility(&self) -> std::io::Result<Accessibility> {
+        if self.ports()?.is_empty() {
+            return Ok(Accessibility::NoPorts);
+        }
+        if self.attr_allow_any_host()? {
+            return Ok(Accessibility::Open);
+        }
+        match self.allowed_hosts()?.count() {
+            0 => Ok(Accessibility::NoHosts),
+            count => Ok(Accessibility::RestrictedToHosts(count)),
+        }
+    }
+
+    /// Return the names, e.g. `nvme0`, of the NVMe host controllers on this machine that are
+    /// connected to this subsystem. This is useful to confirm that a loopback export is live.
+    pub fn live_controllers(&self) -> std::io::Result<Vec<String>> {
+        let paths = match std::fs::read_dir(SYSFS_NVME_DIR) {
+            Ok(paths) => paths,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        let mut controllers = Vec::new();
+        for path in paths {
+            let path = path?;
+            if let Ok(nqn) = read::<_, String>(path.path().join("subsysnqn")) {
+                if nqn == self.nqn() {
+                    controllers.push(path.file_name().to_string_lossy().into_owned());
+                }
+            }
+        }
+        controllers.sort();
+        Ok(controllers)
+    }
+
+    /// Return the ports this subsystem is linked to.
+    pub fn ports(&self) -> std::io::Result<Vec<Port>> {
+        let mut ports = Vec::new();
+        for port in Port::list_all()? {
+            if port.has_subsystem(self)? {
+                ports.push(port);
+            }
+        }
+        Ok(ports)
+    }
+
+    /// Return the passthru configuration of this subsystem.
+    pub fn passthru(&self) -> Passthru {
+        Passthru { path: self.path().join("passthru") }
+    }
+
+    /// Return the hosts allowed to connect to this subsystem.
+    pub fn allowed_hosts(&self) -> std::io::Result<impl Iterator<Item = Host>> {
+        let path = self.path().join("allowed_hosts");
+        let hosts = std::fs::read_dir(path)?;
+        Ok(hosts.map(|host_path| {
+            Host::from_nqn(host_path.unwrap().path().file_name().unwrap().to_os_string())
+        }))
+    }
+
+    pub fn has_allowed_host(&self, host: &Host) -> std::io::Result<bool> {
+        let res = std::fs::read_link(
+            self.path().join("allowed_hosts").join(host.nqn())
+        );
+        match res {
+            Ok(_) => Ok(true),
+            Err(err) => {
+                if err.kind() == std::io::ErrorKind::NotFound {
+                    return Ok(false)
+                }
+                Err(err)
+            }
+        }
+    }
+
+    pub fn add_allowed_host(&self, host: &Host) -> std::io::Result<()> {
+        std::os::unix::fs::symlink(
+            host.path(),
+            self.path().join("allowed_hosts").join(host.nqn())
+        )
+    }
+
+    pub fn remove_allowed_host(&self, nqn: &str) -> std::io::Result<()> {
+        std::fs::remove_file(self.path().join("allowed_hosts").join(nqn))
+    }
+
+    /// Set the firmware revision reported to hosts. The NVMe firmware revision field holds at
+    /// most 8 bytes.
+    pub fn set_attr_firmware(&mut self, value: &str) -> std::io::Result<()> {
+        check_field_len("attr_firmware", value, 8)?;
+        write(probe(self.path().join("attr_firmware"))?, value)
+    }
+
+    pub fn attr_firmware(&self) -> std::result::Result<String, ReadError<String>> {
+        read(probe(self.path().join("attr_firmware")).map_err(ReadError::Io)?)
+    }
+
+    /// Set the NVMe version reported to hosts, e.g. `1.3`.
+    pub fn set_attr_version(&mut self, value: &str) -> std::io::Result<()> {
+        write(self.path().join("attr_version"), value)
+    }
+
+    pub fn attr_version(&self) -> std::result::Result<String, ReadError<String>> {
+        read(self.path().join("attr_version"))
+    }
+
+    /// Set the IEEE OUI reported to hosts, as a hex value such as `0x000a0b`. This is only
+    /// available on kernels that expose an `attr_ieee_oui` subsystem attribute.
+    pub fn set_attr_ieee_oui(&mut self, value: &str) -> std::io::Result<()> {
+        write(probe(self.path().join("attr_ieee_oui"))?, value)
+    }
+
+    pub fn attr_ieee_oui(&self) -> std::result::Result<String, ReadError<String>> {
+        read(probe(self.path().join("attr_ieee_oui")).map_err(ReadError::Io)?)
+    }
+
+    /// Return the configured controller identity of this subsystem. Attributes the running
+    /// kernel does not provide are None.
+    pub fn identity(&self) -> std::io::Result<ControllerIdentity> {
+        let probed = |res: std::result::Result<String, ReadError<String>>| match res {
+            Ok(value) => Ok(Some(value)),
+            Err(ReadError::Io(err)) if err.kind() == std::io::ErrorKind::Unsupported => Ok(None),
+            Err(err) => Err(std::io::Error::from(err)),
+        };
+        Ok(ControllerIdentity {
+            model: Some(self.attr_model()?),
+            serial: Some(self.attr_serial()?),
+            firmware: probed(self.attr_firmware())?,
+            ieee_oui: probed(self.attr_ieee_oui())?,
+            version: Some(self.attr_version()?),
+        })
+    }
+
+    /// Write the fields of `identity` that are Some, leaving the others unchanged. The model,
+    /// serial, and firmware lengths are checked against the identify controller fields, and the
+    /// optional firmware and ieee_oui attributes are probed, before anything is written.
+    pub fn set_identity(&mut self, identity: &ControllerIdentity) -> std::io::Result<()> {
+        if let Some(model) = &identity.model {
+            check_field_len("attr_model", model, 40)?;
+        }
+        if let Some(serial) = &identity.serial {
+            check_field_len("attr_serial", serial, 20)?;
+        }
+        if let Some(firmware) = &identity.firmware {
+            check_field_len("attr_firmware", firmware, 8)?;
+            probe(self.path().join("attr_firmware"))?;
+        }
+        if identity.ieee_oui.is_some() {
+            probe(self.path().join("attr_ieee_oui"))?;
+        }
+        if let Some(model) = &identity.model {
+            self.set_attr_model(model)?;
+        }
+        if let Some(serial) = &identity.serial {
+            self.set_attr_serial(serial)?;
+        }
+        if let Some(firmware) = &identity.firmware {
+            self.set_attr_firmware(firmware)?;
+        }
+        if let Some(ieee_oui) = &identity.ieee_oui {
+            self.set_attr_ieee_oui(ieee_oui)?;
+        }
+        if let Some(version) = &identity.version {
+            self.set_attr_version(version)?;
+        }
+        Ok(())
+    }
+
+    /// Return the optional NVM commands this subsystem advertises. This is only available on
+    /// kernels that expose an `attr_oncs` subsystem attribute.
+    pub fn attr_oncs(&self) -> std::result::Result<CommandSupport, ReadError<CommandSupport>> {
+        read(probe(self.path().join("attr_oncs")).map_err(ReadError::Io)?)
+    }
+
+    /// Return the model, serial, firmware revision, and NVMe version a host would see in the
+    /// identify controller data. Unset or unsupported attributes are replaced by the kernel
+    /// defaults: model `Linux`, the first 8 bytes of the kernel release, and version 1.3.
+    pub fn identify_controller_fields(&self) -> std::io::Result<IdentifyFields> {
+        let mut model = self.attr_model()?;
+        if model.is_empty() {
+            model = "Linux".to_string();
+        }
+        let firmware = match self.attr_firmware() {
+            Ok(firmware) => Some(firmware),
+            Err(ReadError::Io(err)) if err.kind() == std::io::ErrorKind::Unsupported => None,
+            Err(err) => return Err(err.into()),
+        };
+        let firmware = match firmware {
+            Some(firmware) if !firmware.is_empty() => firmware,
+            _ => {
+                let release = std::fs::read_to_string("/proc/sys/kernel/osrelease")?;
+                release.trim_end_matches('\n').chars().take(8).collect()
+            }
+        };
+        let version = match self.read_opt_attr("attr_version")? {
+            Some(version) if !version.is_empty() => version,
+            _ => "1.3".to_string(),
+        };
+        Ok(IdentifyFields {
+            model,
+            serial: self.attr_serial()?,
+            firmware,
+            version,
+        })
+    }
+
+    /// Return the maximum number of namespaces this subsystem supports. This is
+    /// attr_max_namespaces on kernels that expose it, and otherwise the kernel's built-in limit
+    /// of 1024 namespaces per subsystem. Return a `NotFound` error if the subsystem does not
+    /// exist.
+    pub fn max_namespaces(&self) -> std::io::Result<u32> {
+        match self.read_opt_attr("attr_max_namespaces")? {
+            Some(value) => value.parse::<u32>()
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+            None => Ok(1024),
+        }
+    }
+
     pub fn namespaces(&self) -> std::io::Result<impl Iterator<Item = Namespace> + '_> {
         let namespace_dir = self.path().join("namespaces");
         let namespace_paths = std::fs::read_dir(namespace_dir)?;
@@ -273,43 +1582,162 @@ impl Subsystem {
         }))
     }
 
+    /// Return the enable state of the namespace with the given nsid, or None if it does not
+    /// exist.
+    pub fn namespace_enabled(&self, nsid: u32) -> std::io::Result<Option<bool>> {
+        let ns = Namespace { path: self.path().join("namespaces").join(nsid.to_string()) };
+        match ns.enable() {
+            Ok(enable) => Ok(Some(enable)),
+            Err(ReadError::Io(err)) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Return the namespace of this subsystem whose device_path is `device_path`, if any.
+    pub fn namespace_for_device(&self, device_path: &str) -> std::io::Result<Option<Namespace>> {
+        for ns in self.namespaces()? {
+            if ns.device_path()?.as_deref() == Some(device_path) {
+                return Ok(Some(ns));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Return the namespaces of this subsystem keyed by nsid. Entries whose names are not a valid
+    /// nsid are skipped.
+    pub fn namespaces_map(&self) -> std::io::Result<std::collections::HashMap<u32, Namespace>> {
+        Ok(self.namespaces()?
+            .filter_map(|ns| Some((ns.nsid()?, ns)))
+            .collect())
+    }
+
     pub fn list_all() -> std::io::Result<impl Iterator<Item = Subsystem>> {
         let path = std::path::Path::new(CONFIGFS_DIR).join("subsystems");
         let paths = std::fs::read_dir(path)?;
         Ok(paths.map(|path| {
-            Subsystem { nqn: path.unwrap().path().file_name().unwrap().to_os_string() }
+            Subsystem::from_nqn(path.unwrap().path().file_name().unwrap().to_os_string())
         }))
     }
+
+    /// Return the subsystems whose nqn starts with `prefix`. Matching is case-sensitive.
+    pub fn list_with_prefix(prefix: &str) -> std::io::Result<Vec<Subsystem>> {
+        Ok(Subsystem::list_all()?.filter(|subsys| subsys.nqn().starts_with(prefix)).collect())
+    }
+}
+
+/// The passthru configuration of a subsystem, which exports an existing NVMe controller
+/// instead of namespaces.
+pub struct Passthru {
+    path: std::path::PathBuf
+}
+
+impl Passthru {
+    /// Return the Passthru configfs path.
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    /// Write an attribute that can only be changed while passthru is disabled, explaining the
+    /// EBUSY the kernel returns otherwise.
+    fn write_disabled(&mut self, name: &str, value: &str) -> std::io::Result<()> {
+        write(self.path.join(name), value).map_err(|err| {
+            if err.kind() != std::io::ErrorKind::ResourceBusy {
+                return err;
+            }
+            std::io::Error::new(err.kind(), format!("passthru must be disabled to change {}: {}", name, err))
+        })
+    }
+
+    pub fn set_device_path(&mut self, value: &str) -> std::io::Result<()> {
+        self.write_disabled("device_path", value)
+    }
+
+    pub fn device_path(&self) -> std::result::Result<String, ReadError<String>> {
+        read(self.path.join("device_path"))
+    }
+
+    pub fn set_enable(&mut self, value: bool) -> std::io::Result<()> {
+        write(self.path.join("enable"), if value { "1" } else { "0" })
+    }
+
+    pub fn enable(&self) -> std::result::Result<bool, ReadError<u8>> {
+        read(self.path.join("enable")).map(|v| v == 1)
+    }
+
+    /// Set whether the identifiers of the passthru controller, such as its serial, are cleared
+    /// before they are reported to hosts.
+    pub fn set_clear_ids(&mut self, value: bool) -> std::io::Result<()> {
+        self.write_disabled("clear_ids", if value { "1" } else { "0" })
+    }
+
+    pub fn clear_ids(&self) -> std::result::Result<bool, ReadError<u8>> {
+        read(self.path.join("clear_ids")).map(|v| v == 1)
+    }
+
+    /// Set the admin command timeout in seconds, or 0 for the kernel default.
+    pub fn set_admin_timeout(&mut self, value: u32) -> std::io::Result<()> {
+        self.write_disabled("admin_timeout", &value.to_string())
+    }
+
+    pub fn admin_timeout(&self) -> std::result::Result<u32, ReadError<u32>> {
+        read(self.path.join("admin_timeout"))
+    }
+
+    /// Set the I/O command timeout in seconds, or 0 for the kernel default.
+    pub fn set_io_timeout(&mut self, value: u32) -> std::io::Result<()> {
+        self.write_disabled("io_timeout", &value.to_string())
+    }
+
+    pub fn io_timeout(&self) -> std::result::Result<u32, ReadError<u32>> {
+        read(self.path.join("io_timeout"))
+    }
+}
+
+impl AsRef<Path> for Subsystem {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
 }
 
 pub struct Port {
-    id: u32
+    id: u32,
+    path: std::path::PathBuf
 }
 
 impl Port {
     /// Add a new Port with the given id. This will return an error if a Port with the
     /// given id already exists.
     pub fn new(id: u32) -> std::io::Result<Port> {
-        let port = Port { id };
-        std::fs::DirBuilder::new().recursive(true).create(&port.path())?;
+        let port = Port::open(id);
+        std::fs::DirBuilder::new().recursive(true).create(port.path())?;
         Ok(port)
     }
 
     /// Return the Host with the given id. This will not return an error if the host does
     /// not exist.
     pub fn open(id: u32) -> Self {
-        Self { id }
+        let path = std::path::Path::new(CONFIGFS_DIR).join("ports").join(id.to_string());
+        Self { id, path }
     }
 
     /// Return a boolean indicating whether or not a Port with the given id exists.
     pub fn exists(id: u32) -> std::io::Result<bool> {
-        Port { id }.path().try_exists()
+        Port::open(id).path().try_exists()
     }
 
     /// Remove the Port with the given id. This will return an error if a Port with the given
     /// id does not exist.
     pub fn delete(id: u32) -> std::io::Result<()> {
-        std::fs::remove_dir(Port{ id }.path())
+        std::fs::remove_dir(Port::open(id).path())
+    }
+
+    /// Remove every subsystem link, referral, and ANA group from the Port with the given id and
+    /// then remove the Port. If anything could not be removed, the Port is kept and the errors
+    /// are returned together.
+    pub fn delete_recursive(id: u32) -> std::io::Result<()> {
+        let port = Port::open(id);
+        port.clear()?;
+        std::fs::remove_dir(port.path())
     }
 
     /// Return the Port id.
@@ -317,22 +1745,141 @@ impl Port {
         self.id
     }
 
+    /// Return a key identifying this Port for maps and logs, e.g. `port/1`.
+    pub fn key(&self) -> String {
+        format!("port/{}", self.id)
+    }
+
     /// Return the Port configfs path.
     pub fn path(&self) -> std::path::PathBuf {
-        std::path::Path::new(CONFIGFS_DIR).join("ports").join(self.id.to_string())
+        self.path.clone()
+    }
+
+    /// Read the raw value of the attribute `name` in the Port directory, without the trailing
+    /// newline.
+    pub fn get_attr(&self, name: &str) -> std::io::Result<String> {
+        get_attr(&self.path(), name)
+    }
+
+    /// Read the raw value of the attribute `name` in the Port directory, returning None if
+    /// the attribute does not exist and an empty string if it is set to an empty value.
+    pub fn read_opt_attr(&self, name: &str) -> std::io::Result<Option<String>> {
+        read_opt_attr(&self.path(), name)
+    }
+
+    /// Write the raw value of the attribute `name` in the Port directory.
+    pub fn set_attr(&mut self, name: &str, value: &str) -> std::io::Result<()> {
+        set_attr(&self.path(), name, value)
+    }
+
+    /// Return every Port. Directories whose names are not a valid port id are skipped.
+    pub fn list_all() -> std::io::Result<impl Iterator<Item = Port>> {
+        let path = std::path::Path::new(CONFIGFS_DIR).join("ports");
+        let paths = std::fs::read_dir(path)?;
+        Ok(paths.filter_map(|path| {
+            let id = path.unwrap().file_name().to_str()?.parse::<u32>().ok()?;
+            Some(Port::open(id))
+        }))
+    }
+
+    /// Return the ANA group with the given id on this Port. This will not return an error if
+    /// the group does not exist.
+    pub fn ana_group(&self, grpid: u32) -> AnaGroup {
+        AnaGroup { port_path: self.path.clone(), grpid }
+    }
+
+    /// Return the ANA groups of this Port. Directories whose names are not a valid group id are
+    /// skipped.
+    pub fn ana_groups(&self) -> std::io::Result<impl Iterator<Item = AnaGroup>> {
+        let path = self.path().join("ana_groups");
+        let groups = std::fs::read_dir(path)?;
+        let port_path = self.path.clone();
+        Ok(groups.filter_map(move |group_path| {
+            let grpid = group_path.unwrap().file_name().to_str()?.parse::<u32>().ok()?;
+            Some(AnaGroup { port_path: port_path.clone(), grpid })
+        }))
+    }
+
+    /// Return the id and ana_state of every ANA group of this Port, sorted by group id.
+    pub fn ana_group_states(&self) -> std::io::Result<Vec<(u32, AnaState)>> {
+        let mut states = Vec::new();
+        for group in self.ana_groups()? {
+            states.push((group.grpid(), group.state()?));
+        }
+        states.sort_by_key(|(grpid, _)| *grpid);
+        Ok(states)
     }
 
     pub fn subsystems(&self) -> std::io::Result<impl Iterator<Item = Subsystem>> {
         let path = self.path().join("subsystems");
         let subsystems = std::fs::read_dir(path)?;
-        Ok(subsystems.map(|subsys_path| Subsystem {
-            nqn: subsys_path.unwrap().path().file_name().unwrap().to_os_string()
+        Ok(subsystems.map(|subsys_path| {
+            Subsystem::from_nqn(subsys_path.unwrap().path().file_name().unwrap().to_os_string())
         }))
     }
 
+    /// Return the subsystems linked to this Port, naming each after the directory its link points
+    /// to rather than the link itself. Links that no longer point to a subsystem are returned
+    /// separately as the link name and its target.
+    pub fn subsystems_by_target(&self) -> std::io::Result<(Vec<Subsystem>, Vec<DanglingLink>)> {
+        let mut subsystems = Vec::new();
+        let mut dangling = Vec::new();
+        for entry in std::fs::read_dir(self.path().join("subsystems"))? {
+            let entry = entry?;
+            let link = entry.path();
+            let target = std::fs::read_link(&link)?;
+            match target.file_name() {
+                Some(nqn) if link.try_exists()? => subsystems.push(Subsystem::from_nqn(nqn.to_os_string())),
+                _ => dangling.push((entry.file_name().to_string_lossy().into_owned(), target)),
+            }
+        }
+        Ok((subsystems, dangling))
+    }
+
+    /// Return the namespaces of every subsystem linked to this Port. Subsystems whose namespaces
+    /// cannot be listed are returned as skipped.
+    pub fn exposed_namespaces(&self) -> std::io::Result<Scan<(Subsystem, Namespace)>> {
+        let mut scan = Scan { items: Vec::new(), skipped: Vec::new() };
+        for subsys in self.subsystems()? {
+            match subsys.namespaces() {
+                Ok(iter) => {
+                    for ns in iter {
+                        scan.items.push((Subsystem::open(subsys.nqn()), ns));
+                    }
+                }
+                Err(err) => scan.skipped.push((subsys.path(), err)),
+            }
+        }
+        Ok(scan)
+    }
+
+    /// Return the number of subsystems linked to this Port without building Subsystem handles.
+    pub fn subsystem_count(&self) -> std::io::Result<usize> {
+        let mut count = 0;
+        for entry in std::fs::read_dir(self.path().join("subsystems"))? {
+            entry?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Classify this Port by whether subsystems, referrals, or both are configured on it. The
+    /// discovery subsystem cannot be linked to a port, so discovery behavior is determined by
+    /// the referrals the port advertises in its discovery log.
+    pub fn role(&self) -> std::io::Result<PortRole> {
+        let subsystems = self.subsystem_count()? > 0;
+        let referrals = self.referrals()?.next().is_some();
+        Ok(match (subsystems, referrals) {
+            (false, false) => PortRole::Unconfigured,
+            (false, true) => PortRole::DiscoveryOnly,
+            (true, false) => PortRole::DataServing,
+            (true, true) => PortRole::Mixed,
+        })
+    }
+
     pub fn has_subsystem(&self, subsys: &Subsystem) -> std::io::Result<bool> {
         let res = std::fs::read_link(
-            self.path().join("subsystems").join(&subsys.nqn())
+            self.path().join("subsystems").join(subsys.nqn())
         );
         match res {
             Ok(_) => Ok(true),
@@ -345,10 +1892,32 @@ impl Port {
         }
     }
 
+    /// Remove the subsystem links of this Port whose targets no longer exist, e.g. because the
+    /// subsystem was deleted without being unlinked first. Return the nqns of the removed links.
+    pub fn prune_dangling_subsystems(&self) -> std::io::Result<Vec<String>> {
+        let mut removed = Vec::new();
+        for subsys in self.subsystems()? {
+            if !self.has_working_subsystem(&subsys)? {
+                self.remove_subsystem(subsys.nqn())?;
+                removed.push(subsys.nqn().to_string());
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Return whether `subsys` is linked to this Port and the link target still exists. Unlike
+    /// `has_subsystem`, a dangling link left behind by a removed subsystem reports false.
+    pub fn has_working_subsystem(&self, subsys: &Subsystem) -> std::io::Result<bool> {
+        if !self.has_subsystem(subsys)? {
+            return Ok(false);
+        }
+        self.path().join("subsystems").join(subsys.nqn()).try_exists()
+    }
+
     pub fn add_subsystem(&self, subsys: &Subsystem) -> std::io::Result<()> {
         std::os::unix::fs::symlink(
             subsys.path(), 
-            self.path().join("subsystems").join(&subsys.nqn())
+            self.path().join("subsystems").join(subsys.nqn())
         )
     }
 
@@ -356,42 +1925,316 @@ impl Port {
         std::fs::remove_file(self.path().join("subsystems").join(nqn))
     }
 
-    pub fn set_addr_adrfam(&mut self, value: &str) -> std::io::Result<()> {
-        let path = self.path().join("addr_adrfam");
-        let mut file = std::fs::File::create(path)?;
-        let value_string = value.to_string() + "\n";
-        file.write_all(value_string.as_bytes())?;
-        Ok(())
+    pub fn referrals(&self) -> std::io::Result<impl Iterator<Item = Referral>> {
+        let path = self.path().join("referrals");
+        let referrals = std::fs::read_dir(path)?;
+        let port_path = self.path.clone();
+        Ok(referrals.map(move |referral_path| Referral {
+            port_path: port_path.clone(),
+            name: referral_path.unwrap().file_name()
+        }))
     }
 
-    pub fn addr_adrfam(&self)-> std::result::Result<String, ReadError<String>> {
-        read(self.path().join("addr_adrfam"))
+    /// Create a referral with the given name on this Port. Return an error if a referral with
+    /// the given name already exists.
+    pub fn create_referral(&self, name: &str) -> std::io::Result<Referral> {
+        let referral = Referral { port_path: self.path.clone(), name: std::ffi::OsString::from(name) };
+        std::fs::create_dir(referral.path())?;
+        Ok(referral)
     }
 
-    pub fn set_addr_traddr(&mut self, value: &str) -> std::io::Result<()> {
-        let path = self.path().join("addr_traddr");
-        let mut file = std::fs::File::create(path)?;
-        let value_string = value.to_string() + "\n";
-        file.write_all(value_string.as_bytes())?;
-        Ok(())
+    /// Return the name, address, and enable state of every referral of this Port.
+    pub fn referrals_detailed(&self) -> std::io::Result<Vec<ReferralInfo>> {
+        Ok(self.referrals()?.map(|referral| ReferralInfo {
+            name: referral.name().to_string(),
+            adrfam: referral.addr_adrfam().ok(),
+            traddr: referral.addr_traddr().ok(),
+            trsvcid: referral.addr_trsvcid().ok(),
+            trtype: referral.addr_trtype().ok(),
+            enable: referral.enable().ok(),
+        }).collect())
     }
 
-    pub fn addr_traddr(&self)-> std::result::Result<String, ReadError<String>> {
-        read(self.path().join("addr_traddr"))
+    /// Return the referral with the given name on this Port. This will not return an error if
+    /// the referral does not exist.
+    pub fn referral(&self, name: &str) -> Referral {
+        Referral { port_path: self.path.clone(), name: std::ffi::OsString::from(name) }
     }
 
-    pub fn set_addr_trsvcid(&mut self, value: &str) -> std::io::Result<()> {
-        let path = self.path().join("addr_trsvcid");
-        let mut file = std::fs::File::create(path)?;
-        let value_string = value.to_string() + "\n";
-        file.write_all(value_string.as_bytes())?;
-        Ok(())
+    pub fn delete_referral(&self, name: &str) -> std::io::Result<()> {
+        std::fs::remove_dir(self.path().join("referrals").join(name))
     }
 
-    pub fn addr_trsvcid(&self)-> std::result::Result<String, ReadError<String>> {
+    /// Reconcile the referrals of this Port with `specs`. Referrals not in `specs` are removed,
+    /// missing ones are created, and any whose address or enable state differs is reconfigured.
+    pub fn set_referrals(&self, specs: &[ReferralSpec]) -> std::io::Result<()> {
+        for referral in self.referrals()? {
+            if !specs.iter().any(|spec| spec.name == referral.name()) {
+                self.delete_referral(referral.name())?;
+            }
+        }
+        for spec in specs {
+            let mut referral = self.referral(&spec.name);
+            if !referral.path().try_exists()? {
+                referral = self.create_referral(&spec.name)?;
+            } else if referral.enable()?
+                && referral.addr_adrfam()? == spec.adrfam
+                && referral.addr_traddr()? == spec.traddr
+                && referral.addr_trsvcid()? == spec.trsvcid
+                && referral.addr_trtype()? == spec.trtype
+            {
+                continue;
+            }
+            referral.configure(&spec.adrfam, &spec.traddr, &spec.trsvcid, &spec.trtype)?;
+        }
+        Ok(())
+    }
+
+    /// Remove every subsystem link, referral, and ANA group other than the default group 1 from
+    /// this Port, continuing past failures.
+    fn clear(&self) -> std::io::Result<()> {
+        let mut errors = Vec::new();
+        for subsys in self.subsystems()? {
+            errors.extend(self.remove_subsystem(subsys.nqn()).err());
+        }
+        for referral in self.referrals()? {
+            errors.extend(self.delete_referral(referral.name()).err());
+        }
+        for group in self.ana_groups()? {
+            if group.grpid() != 1 {
+                errors.extend(std::fs::remove_dir(group.path()).err());
+            }
+        }
+        aggregate("clearing port", errors)
+    }
+
+    /// Capture the address, params, linked subsystems, and referrals of this Port.
+    pub fn snapshot(&self) -> std::io::Result<PortSnapshot> {
+        let mut params = std::collections::BTreeMap::new();
+        for entry in std::fs::read_dir(self.path())? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if name.starts_with("param_") {
+                let value = self.get_attr(&name)?;
+                params.insert(name, value);
+            }
+        }
+        let mut subsystems: Vec<String> = self.subsystems()?.map(|subsys| subsys.nqn().to_string()).collect();
+        subsystems.sort();
+        let mut referrals = Vec::new();
+        for referral in self.referrals()? {
+            referrals.push(ReferralSnapshot {
+                name: referral.name().to_string(),
+                addr_adrfam: referral.addr_adrfam()?,
+                addr_traddr: referral.addr_traddr()?,
+                addr_trsvcid: referral.addr_trsvcid()?,
+                addr_trtype: referral.addr_trtype()?,
+                enable: referral.enable()?,
+            });
+        }
+        Ok(PortSnapshot {
+            addr_adrfam: self.addr_adrfam()?,
+            addr_traddr: self.addr_traddr()?,
+            addr_trsvcid: self.addr_trsvcid()?,
+            addr_trtype: self.addr_trtype()?,
+            params,
+            subsystems,
+            referrals,
+        })
+    }
+
+    pub fn set_addr_adrfam(&mut self, value: &str) -> std::io::Result<()> {
+        let path = self.path().join("addr_adrfam");
+        let mut file = std::fs::File::create(path)?;
+        let value_string = value.to_string() + "\n";
+        file.write_all(value_string.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn addr_adrfam(&self)-> std::result::Result<String, ReadError<String>> {
+        read(self.path().join("addr_adrfam"))
+    }
+
+    pub fn set_addr_traddr(&mut self, value: &str) -> std::io::Result<()> {
+        let path = self.path().join("addr_traddr");
+        let mut file = std::fs::File::create(path)?;
+        let value_string = value.to_string() + "\n";
+        file.write_all(value_string.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn addr_traddr(&self)-> std::result::Result<String, ReadError<String>> {
+        read(self.path().join("addr_traddr"))
+    }
+
+    /// Set the transport service id. On tcp and rdma ports the service id is an IP port
+    /// number, so non-numeric values are rejected before they reach the kernel.
+    pub fn set_addr_trsvcid(&mut self, value: &str) -> std::io::Result<()> {
+        if let Ok(trtype) = self.addr_trtype() {
+            if (trtype == "tcp" || trtype == "rdma") && value.parse::<u16>().is_err() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("trsvcid {:?} is not a valid port number for the {} transport", value, trtype)
+                ));
+            }
+        }
+        let path = self.path().join("addr_trsvcid");
+        let mut file = std::fs::File::create(path)?;
+        let value_string = value.to_string() + "\n";
+        file.write_all(value_string.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn addr_trsvcid(&self)-> std::result::Result<String, ReadError<String>> {
         read(self.path().join("addr_trsvcid"))
     }
 
+    /// Set the transport specific address subtype. Its meaning depends on the transport, e.g.
+    /// the TLS mode for tcp, so the value is passed through unchanged.
+    pub fn set_addr_tsas(&mut self, value: &str) -> std::io::Result<()> {
+        write(self.path().join("addr_tsas"), value)
+    }
+
+    pub fn addr_tsas(&self) -> std::result::Result<String, ReadError<String>> {
+        read(self.path().join("addr_tsas"))
+    }
+
+    /// Set whether the transport requires a secure channel, e.g. TLS on tcp.
+    pub fn set_treq(&mut self, value: &TransportRequirements) -> std::io::Result<()> {
+        write(self.path().join("addr_treq"), &value.to_string())
+    }
+
+    pub fn treq(&self) -> std::result::Result<TransportRequirements, ReadError<TransportRequirements>> {
+        read(self.path().join("addr_treq"))
+    }
+
+    /// Return the transport type and address of this Port.
+    pub fn transport_info(&self) -> std::io::Result<TransportInfo> {
+        let field = |name: &str| -> std::io::Result<Option<String>> {
+            match read::<_, String>(self.path().join(name)) {
+                Ok(value) if value.is_empty() => Ok(None),
+                Ok(value) => Ok(Some(value)),
+                Err(ReadError::Io(err)) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(err) => Err(err.into()),
+            }
+        };
+        Ok(TransportInfo {
+            trtype: field("addr_trtype")?,
+            adrfam: field("addr_adrfam")?,
+            traddr: field("addr_traddr")?,
+            trsvcid: field("addr_trsvcid")?,
+        })
+    }
+
+    /// Write the fields of `info` that are set, transport type first.
+    fn write_transport_info(&mut self, info: &TransportInfo) -> std::io::Result<()> {
+        if let Some(trtype) = &info.trtype {
+            self.set_addr_trtype(trtype)?;
+        }
+        if let Some(adrfam) = &info.adrfam {
+            self.set_addr_adrfam(adrfam)?;
+        }
+        if let Some(traddr) = &info.traddr {
+            self.set_addr_traddr(traddr)?;
+        }
+        if let Some(trsvcid) = &info.trsvcid {
+            self.set_addr_trsvcid(trsvcid)?;
+        }
+        Ok(())
+    }
+
+    /// Change the transport type and address of this Port. The address can only be written
+    /// while no subsystems are linked, so the linked subsystems are unlinked, the address is
+    /// written, and the same subsystems are linked again. On failure the previous address and
+    /// links are restored.
+    pub fn reconfigure_address(&mut self, info: TransportInfo) -> std::io::Result<()> {
+        let previous = self.transport_info()?;
+        let linked: Vec<Subsystem> = self.subsystems()?.collect();
+        let res = linked.iter().try_for_each(|subsys| self.remove_subsystem(subsys.nqn()))
+            .and_then(|_| self.write_transport_info(&info))
+            .and_then(|_| linked.iter().try_for_each(|subsys| self.add_subsystem(subsys)));
+        if res.is_err() {
+            for subsys in &linked {
+                let _ = self.remove_subsystem(subsys.nqn());
+            }
+            let _ = self.write_transport_info(&previous);
+            for subsys in &linked {
+                let _ = self.add_subsystem(subsys);
+            }
+        }
+        res
+    }
+
+    /// Configure this Port as a Fibre Channel port with the given world wide node and port names.
+    pub fn set_fc_address(&mut self, wwnn: u64, wwpn: u64) -> std::io::Result<()> {
+        self.set_addr_trtype("fc")?;
+        self.set_addr_adrfam("fc")?;
+        self.set_addr_traddr(&format!("nn-0x{:016x}:pn-0x{:016x}", wwnn, wwpn))
+    }
+
+    /// Return the world wide node and port names of a Fibre Channel port, or None if the
+    /// transport address is not in the `nn-0x...:pn-0x...` format with 1 to 16 hex digits per
+    /// name.
+    pub fn fc_address(&self) -> std::io::Result<Option<(u64, u64)>> {
+        let traddr = self.addr_traddr()?;
+        let parse = |value: &str, prefix: &str| {
+            let hex = value.strip_prefix(prefix)?;
+            if !(1..=16).contains(&hex.len()) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            u64::from_str_radix(hex, 16).ok()
+        };
+        Ok(traddr.split_once(':').and_then(|(nn, pn)| {
+            Some((parse(nn, "nn-0x")?, parse(pn, "pn-0x")?))
+        }))
+    }
+
+    /// Write a probed attribute that can only be changed while no subsystems are linked,
+    /// explaining the error the kernel returns otherwise.
+    fn write_unlinked(&mut self, name: &str, value: &str) -> std::io::Result<()> {
+        write(probe(self.path().join(name))?, value).map_err(|err| {
+            match err.kind() {
+                std::io::ErrorKind::ResourceBusy | std::io::ErrorKind::PermissionDenied => std::io::Error::new(
+                    err.kind(),
+                    format!("{} can only be changed while no subsystems are linked to port {}: {}", name, self.id, err)
+                ),
+                _ => err,
+            }
+        })
+    }
+
+    /// Set whether T10 protection information is enabled on this Port.
+    pub fn set_param_pi_enable(&mut self, value: bool) -> std::io::Result<()> {
+        self.write_unlinked("param_pi_enable", if value { "1" } else { "0" })
+    }
+
+    pub fn param_pi_enable(&self) -> std::result::Result<bool, ReadError<u8>> {
+        read(probe(self.path().join("param_pi_enable")).map_err(ReadError::Io)?).map(|v: u8| v == 1)
+    }
+
+    /// Set whether this Port offloads I/O to capable RDMA or FC hardware.
+    pub fn set_param_offload(&mut self, value: bool) -> std::io::Result<()> {
+        self.write_unlinked("param_offload", if value { "1" } else { "0" })
+    }
+
+    pub fn param_offload(&self) -> std::result::Result<bool, ReadError<u8>> {
+        read(probe(self.path().join("param_offload")).map_err(ReadError::Io)?).map(|v: u8| v == 1)
+    }
+
+    /// Set the keyring reference, by serial or description, of the TLS PSK used by this Port.
+    /// This is only available on kernels that expose a `tls_key` port attribute.
+    pub fn set_tls_key(&mut self, value: &str) -> std::io::Result<()> {
+        write(probe(self.path().join("tls_key"))?, value)
+    }
+
+    pub fn tls_key(&self) -> std::result::Result<String, ReadError<String>> {
+        read(probe(self.path().join("tls_key")).map_err(ReadError::Io)?)
+    }
+
+    /// Set the transport service id to the given IP port number.
+    pub fn set_trsvcid_port(&mut self, port: u16) -> std::io::Result<()> {
+        self.set_addr_trsvcid(&port.to_string())
+    }
+
     pub fn set_addr_trtype(&mut self, value: &str) -> std::io::Result<()> {
         let path = self.path().join("addr_trtype");
         let mut file = std::fs::File::create(path)?;
@@ -405,28 +2248,385 @@ impl Port {
     }
 }
 
+/// A link that no longer points to an existing directory, as the link name and its target.
+pub type DanglingLink = (String, std::path::PathBuf);
+
+/// What a Port serves, as returned by [`Port::role`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PortRole {
+    /// Neither subsystems nor referrals are configured on the port.
+    Unconfigured,
+    /// The port only has referrals, so its discovery log points hosts at other ports.
+    DiscoveryOnly,
+    /// The port only has linked subsystems.
+    DataServing,
+    /// The port has both linked subsystems and referrals.
+    Mixed,
+}
+
+/// The transport type and address of a Port. Fields that are unset are None.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TransportInfo {
+    pub trtype: Option<String>,
+    pub adrfam: Option<String>,
+    pub traddr: Option<String>,
+    pub trsvcid: Option<String>,
+}
+
+/// The state of a Port captured by [`Port::snapshot`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PortSnapshot {
+    pub addr_adrfam: String,
+    pub addr_traddr: String,
+    pub addr_trsvcid: String,
+    pub addr_trtype: String,
+    /// The `param_*` attributes of the Port keyed by attribute name.
+    pub params: std::collections::BTreeMap<String, String>,
+    /// The nqns of the subsystems linked to the Port.
+    pub subsystems: Vec<String>,
+    pub referrals: Vec<ReferralSnapshot>,
+}
+
+/// The state of a Referral captured as part of a [`PortSnapshot`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReferralSnapshot {
+    pub name: String,
+    pub addr_adrfam: String,
+    pub addr_traddr: String,
+    pub addr_trsvcid: String,
+    pub addr_trtype: String,
+    pub enable: bool,
+}
+
+impl PortSnapshot {
+    /// Reconcile `port` with this snapshot. The address and params can only be written while no
+    /// subsystems are linked, so if they differ every link is removed first. Afterwards missing
+    /// subsystem links and referrals are added and extra ones are removed.
+    pub fn restore(&self, port: &mut Port) -> std::io::Result<()> {
+        let current = port.snapshot()?;
+        if current.addr_adrfam != self.addr_adrfam
+            || current.addr_traddr != self.addr_traddr
+            || current.addr_trsvcid != self.addr_trsvcid
+            || current.addr_trtype != self.addr_trtype
+            || current.params != self.params
+        {
+            for nqn in &current.subsystems {
+                port.remove_subsystem(nqn)?;
+            }
+            let addr = [
+                ("addr_trtype", &self.addr_trtype),
+                ("addr_adrfam", &self.addr_adrfam),
+                ("addr_traddr", &self.addr_traddr),
+                ("addr_trsvcid", &self.addr_trsvcid),
+            ];
+            for (name, value) in addr.into_iter().chain(self.params.iter().map(|(k, v)| (k.as_str(), v))) {
+                if !value.is_empty() && port.get_attr(name)? != *value {
+                    port.set_attr(name, value)?;
+                }
+            }
+        }
+        for subsys in port.subsystems()? {
+            if !self.subsystems.iter().any(|nqn| nqn == subsys.nqn()) {
+                port.remove_subsystem(subsys.nqn())?;
+            }
+        }
+        for nqn in &self.subsystems {
+            let subsys = Subsystem::open(nqn.as_str());
+            if !port.has_subsystem(&subsys)? {
+                port.add_subsystem(&subsys)?;
+            }
+        }
+        for referral in port.referrals()? {
+            if !self.referrals.iter().any(|snapshot| snapshot.name == referral.name()) {
+                port.delete_referral(referral.name())?;
+            }
+        }
+        for snapshot in &self.referrals {
+            let mut referral = match current.referrals.iter().find(|r| r.name == snapshot.name) {
+                Some(current) if current == snapshot => continue,
+                Some(_) => port.referral(&snapshot.name),
+                None => port.create_referral(&snapshot.name)?,
+            };
+            referral.set_enable(false)?;
+            if !snapshot.addr_trtype.is_empty() {
+                referral.set_addr_trtype(&snapshot.addr_trtype)?;
+            }
+            if !snapshot.addr_adrfam.is_empty() {
+                referral.set_addr_adrfam(&snapshot.addr_adrfam)?;
+            }
+            if !snapshot.addr_traddr.is_empty() {
+                referral.set_addr_traddr(&snapshot.addr_traddr)?;
+            }
+            if !snapshot.addr_trsvcid.is_empty() {
+                referral.set_addr_trsvcid(&snapshot.addr_trsvcid)?;
+            }
+            referral.set_enable(snapshot.enable)?;
+        }
+        Ok(())
+    }
+}
+
+/// The ANA state of an ANA group.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AnaState {
+    Optimized,
+    NonOptimized,
+    Inaccessible,
+    PersistentLoss,
+    Change,
+    /// A state not known to this crate.
+    Other(String),
+}
+
+impl std::fmt::Display for AnaState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AnaState::Optimized => f.write_str("optimized"),
+            AnaState::NonOptimized => f.write_str("non-optimized"),
+            AnaState::Inaccessible => f.write_str("inaccessible"),
+            AnaState::PersistentLoss => f.write_str("persistent-loss"),
+            AnaState::Change => f.write_str("change"),
+            AnaState::Other(state) => f.write_str(state),
+        }
+    }
+}
+
+impl std::str::FromStr for AnaState {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s {
+            "optimized" => AnaState::Optimized,
+            "non-optimized" => AnaState::NonOptimized,
+            "inaccessible" => AnaState::Inaccessible,
+            "persistent-loss" => AnaState::PersistentLoss,
+            "change" => AnaState::Change,
+            state => AnaState::Other(state.to_string()),
+        })
+    }
+}
+
+/// The secure channel requirements of a Port, as stored in `addr_treq`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TransportRequirements {
+    NotSpecified,
+    Required,
+    NotRequired,
+    /// A value not known to this crate.
+    Other(String),
+}
+
+impl std::fmt::Display for TransportRequirements {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransportRequirements::NotSpecified => f.write_str("not specified"),
+            TransportRequirements::Required => f.write_str("required"),
+            TransportRequirements::NotRequired => f.write_str("not required"),
+            TransportRequirements::Other(treq) => f.write_str(treq),
+        }
+    }
+}
+
+impl std::str::FromStr for TransportRequirements {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s {
+            "not specified" => TransportRequirements::NotSpecified,
+            "required" => TransportRequirements::Required,
+            "not required" => TransportRequirements::NotRequired,
+            treq => TransportRequirements::Other(treq.to_string()),
+        })
+    }
+}
+
+impl AsRef<Path> for Port {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+pub struct AnaGroup {
+    port_path: std::path::PathBuf,
+    grpid: u32
+}
+
+impl AnaGroup {
+    /// Return the ANA group id.
+    pub fn grpid(&self) -> u32 {
+        self.grpid
+    }
+
+    /// Return the AnaGroup configfs path.
+    pub fn path(&self) -> std::path::PathBuf {
+        self.port_path.join("ana_groups").join(self.grpid.to_string())
+    }
+
+    pub fn set_state(&mut self, value: &AnaState) -> std::io::Result<()> {
+        write(self.path().join("ana_state"), &value.to_string())
+    }
+
+    pub fn state(&self) -> std::result::Result<AnaState, ReadError<AnaState>> {
+        read(self.path().join("ana_state"))
+    }
+}
+
+pub struct Referral {
+    port_path: std::path::PathBuf,
+    name: std::ffi::OsString
+}
+
+impl Referral {
+    /// Return the Referral name.
+    pub fn name(&self) -> &str {
+        self.name.to_str().unwrap()
+    }
+
+    /// Return the Referral configfs path.
+    pub fn path(&self) -> std::path::PathBuf {
+        self.port_path.join("referrals").join(&self.name)
+    }
+
+    pub fn set_enable(&mut self, value: bool) -> std::io::Result<()> {
+        write(self.path().join("enable"), if value { "1" } else { "0" })
+    }
+
+    pub fn enable(&self) -> std::result::Result<bool, ReadError<u8>> {
+        read(self.path().join("enable")).map(|v| v == 1)
+    }
+
+    pub fn set_addr_adrfam(&mut self, value: &str) -> std::io::Result<()> {
+        write(self.path().join("addr_adrfam"), value)
+    }
+
+    pub fn addr_adrfam(&self) -> std::result::Result<String, ReadError<String>> {
+        read(self.path().join("addr_adrfam"))
+    }
+
+    pub fn set_addr_traddr(&mut self, value: &str) -> std::io::Result<()> {
+        write(self.path().join("addr_traddr"), value)
+    }
+
+    pub fn addr_traddr(&self) -> std::result::Result<String, ReadError<String>> {
+        read(self.path().join("addr_traddr"))
+    }
+
+    pub fn set_addr_trsvcid(&mut self, value: &str) -> std::io::Result<()> {
+        write(self.path().join("addr_trsvcid"), value)
+    }
+
+    pub fn addr_trsvcid(&self) -> std::result::Result<String, ReadError<String>> {
+        read(self.path().join("addr_trsvcid"))
+    }
+
+    pub fn set_addr_trtype(&mut self, value: &str) -> std::io::Result<()> {
+        write(self.path().join("addr_trtype"), value)
+    }
+
+    pub fn addr_trtype(&self) -> std::result::Result<String, ReadError<String>> {
+        read(self.path().join("addr_trtype"))
+    }
+
+    /// Write all address fields and then enable the Referral. A Referral can only be addressed
+    /// while disabled, so it is disabled first.
+    pub fn configure(&mut self, adrfam: &str, traddr: &str, trsvcid: &str, trtype: &str) -> std::io::Result<()> {
+        self.set_enable(false)?;
+        self.set_addr_trtype(trtype)?;
+        self.set_addr_adrfam(adrfam)?;
+        self.set_addr_traddr(traddr)?;
+        self.set_addr_trsvcid(trsvcid)?;
+        self.set_enable(true)
+    }
+}
+
+/// The configuration of a Referral returned by [`Port::referrals_detailed`]. Fields that could
+/// not be read are None.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReferralInfo {
+    pub name: String,
+    pub adrfam: Option<String>,
+    pub traddr: Option<String>,
+    pub trsvcid: Option<String>,
+    pub trtype: Option<String>,
+    pub enable: Option<bool>,
+}
+
+/// A description of an enabled referral used by [`Port::set_referrals`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReferralSpec {
+    pub name: String,
+    pub adrfam: String,
+    pub traddr: String,
+    pub trsvcid: String,
+    pub trtype: String,
+}
+
 pub struct Host {
-    nqn: std::ffi::OsString
+    nqn: std::ffi::OsString,
+    path: std::path::PathBuf
 }
 
 impl Host {
+    fn from_nqn(nqn: std::ffi::OsString) -> Host {
+        let path = std::path::Path::new(CONFIGFS_DIR).join("hosts").join(&nqn);
+        Host { nqn, path }
+    }
+
     /// Add a new Host with the given nqn. This will fail if a host with the
     /// given nqn already exists.
     pub fn new<T>(nqn: T) -> std::io::Result<Self> 
     where 
         std::ffi::OsString: From<T>
     {
-        let host = Self { nqn: std::ffi::OsString::from(nqn) };
-        std::fs::create_dir(&host.path())?;
+        let host = Self::from_nqn(std::ffi::OsString::from(nqn));
+        std::fs::create_dir(host.path())?;
+        Ok(host)
+    }
+
+    /// Add a new Host with the given nqn and add it to the allowed_hosts of each of
+    /// `subsystems`. Return a `NotFound` error before creating anything if one of the subsystems
+    /// does not exist. If the host cannot be added to a subsystem, the links made so far and the
+    /// host are removed again.
+    pub fn new_with_access<T>(nqn: T, subsystems: &[&Subsystem]) -> std::io::Result<Self>
+    where
+        std::ffi::OsString: From<T>
+    {
+        for subsys in subsystems {
+            if !subsys.path().try_exists()? {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("subsystem {} does not exist", subsys.nqn())
+                ));
+            }
+        }
+        let host = Self::new(nqn)?;
+        for (i, subsys) in subsystems.iter().enumerate() {
+            if let Err(err) = subsys.add_allowed_host(&host) {
+                for subsys in &subsystems[..i] {
+                    let _ = subsys.remove_allowed_host(host.nqn());
+                }
+                let _ = std::fs::remove_dir(host.path());
+                return Err(err);
+            }
+        }
         Ok(host)
     }
 
+    /// Return the Host with the given nqn. This will not return an error if the host does
+    /// not exist.
+    pub fn open<T>(nqn: T) -> Self
+    where
+        std::ffi::OsString: From<T>
+    {
+        Self::from_nqn(std::ffi::OsString::from(nqn))
+    }
+
     /// Return a boolean indicating whether or not a Host with the given nqn exists.
     pub fn exists<T>(nqn: T) -> std::io::Result<bool>
     where 
         std::ffi::OsString: From<T>
     {
-        Self { nqn: std::ffi::OsString::from(nqn) }.path().try_exists()
+        Self::from_nqn(std::ffi::OsString::from(nqn)).path().try_exists()
     }
 
     /// Remove the Host with the given nqn. This will return an error if a host
@@ -435,17 +2635,1191 @@ impl Host {
     where 
         std::ffi::OsString: From<T>
     {
-        let host = Self { nqn: std::ffi::OsString::from(nqn) };
+        let host = Self::from_nqn(std::ffi::OsString::from(nqn));
         std::fs::remove_dir(host.path())
     }
 
     /// Return the Host configfs path.
     pub fn path(&self) -> std::path::PathBuf {
-        std::path::Path::new(CONFIGFS_DIR).join("hosts").join(self.nqn.clone())
+        self.path.clone()
+    }
+
+    pub fn list_all() -> std::io::Result<impl Iterator<Item = Host>> {
+        let path = std::path::Path::new(CONFIGFS_DIR).join("hosts");
+        let paths = std::fs::read_dir(path)?;
+        Ok(paths.map(|path| {
+            Host::from_nqn(path.unwrap().path().file_name().unwrap().to_os_string())
+        }))
+    }
+
+    /// Read the raw value of the attribute `name` in the Host directory, without the trailing
+    /// newline.
+    pub fn get_attr(&self, name: &str) -> std::io::Result<String> {
+        get_attr(&self.path(), name)
+    }
+
+    /// Read the raw value of the attribute `name` in the Host directory, returning None if
+    /// the attribute does not exist and an empty string if it is set to an empty value.
+    pub fn read_opt_attr(&self, name: &str) -> std::io::Result<Option<String>> {
+        read_opt_attr(&self.path(), name)
+    }
+
+    /// Write the raw value of the attribute `name` in the Host directory.
+    pub fn set_attr(&mut self, name: &str, value: &str) -> std::io::Result<()> {
+        set_attr(&self.path(), name, value)
     }
 
     /// Return the Host nqn.
-    pub fn nqn<'a>(&'a self) -> &'a str {
-        &self.nqn.to_str().unwrap()
+    pub fn nqn(&self) -> &str {
+        self.nqn.to_str().unwrap()
+    }
+
+    /// Copy the dhchap settings of this Host to `other`. Unset keys are skipped.
+    fn copy_dhchap_to(&self, other: &Host) -> std::io::Result<()> {
+        for attr in ["dhchap_hash", "dhchap_dhgroup", "dhchap_key", "dhchap_ctrl_key"] {
+            let value = match std::fs::read_to_string(self.path().join(attr)) {
+                Ok(value) => value,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+            if value.trim().is_empty() {
+                continue;
+            }
+            let mut file = std::fs::File::create(other.path().join(attr))?;
+            file.write_all(value.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Replace this Host with a Host named `new_nqn`. The dhchap settings are copied and every
+    /// subsystem that allows this Host is updated to allow the new Host before this Host is
+    /// deleted. Return a `NotFound` error before creating anything if this Host does not exist.
+    /// If any step fails, this Host is allowed again wherever it was unlinked, and the new Host
+    /// and its links are removed.
+    pub fn rename<T>(self, new_nqn: T) -> std::io::Result<Host>
+    where
+        std::ffi::OsString: From<T>
+    {
+        if !self.path().try_exists()? {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("host {} does not exist", self.nqn())
+            ));
+        }
+        let new_host = Host::new(new_nqn)?;
+        let mut linked: Vec<Subsystem> = Vec::new();
+        let mut unlinked = 0;
+        let res = self.copy_dhchap_to(&new_host).and_then(|_| {
+            for subsys in Subsystem::list_all()? {
+                if subsys.has_allowed_host(&self)? {
+                    subsys.add_allowed_host(&new_host)?;
+                    linked.push(subsys);
+                }
+            }
+            for subsys in &linked {
+                subsys.remove_allowed_host(self.nqn())?;
+                unlinked += 1;
+            }
+            std::fs::remove_dir(self.path())
+        });
+        if let Err(err) = res {
+            for subsys in &linked[..unlinked] {
+                let _ = subsys.add_allowed_host(&self);
+            }
+            for subsys in &linked {
+                let _ = subsys.remove_allowed_host(new_host.nqn());
+            }
+            let _ = std::fs::remove_dir(new_host.path());
+            return Err(err);
+        }
+        Ok(new_host)
+    }
+}
+
+impl AsRef<Path> for Host {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// A namespace identified by the nqn of its subsystem and its nsid.
+pub type NamespaceId = (String, u32);
+
+/// Return each device_uuid that is used by more than one namespace, together with the subsystem
+/// nqn and nsid of every namespace using it.
+pub fn find_duplicate_uuids() -> std::io::Result<Vec<(String, Vec<NamespaceId>)>> {
+    let mut users: std::collections::BTreeMap<String, Vec<NamespaceId>> = std::collections::BTreeMap::new();
+    for subsys in Subsystem::list_all()? {
+        for ns in subsys.namespaces()? {
+            let Some(nsid) = ns.nsid() else { continue };
+            let uuid = ns.device_uuid()?;
+            users.entry(uuid).or_default().push((subsys.nqn().to_string(), nsid));
+        }
+    }
+    Ok(users.into_iter().filter(|(_, users)| users.len() > 1).collect())
+}
+
+/// A discovery log entry that a discovery controller would present for a subsystem exported on
+/// a port.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiscoveryEntry {
+    pub port_id: u32,
+    pub trtype: String,
+    pub adrfam: String,
+    pub traddr: String,
+    pub trsvcid: String,
+    pub subnqn: String,
+}
+
+/// Return the discovery log entries derived from the configured ports and the subsystems linked
+/// to them. This reflects the configfs state only and does not query a live discovery controller.
+pub fn discovery_entries() -> std::io::Result<Vec<DiscoveryEntry>> {
+    let mut entries = Vec::new();
+    for port in Port::list_all()? {
+        for subsys in port.subsystems()? {
+            entries.push(DiscoveryEntry {
+                port_id: port.id(),
+                trtype: port.addr_trtype()?,
+                adrfam: port.addr_adrfam()?,
+                traddr: port.addr_traddr()?,
+                trsvcid: port.addr_trsvcid()?,
+                subnqn: subsys.nqn().to_string(),
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// The transport type of a port.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TransportType {
+    Tcp,
+    Rdma,
+    Fc,
+    Loop,
+}
+
+impl std::fmt::Display for TransportType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransportType::Tcp => f.write_str("tcp"),
+            TransportType::Rdma => f.write_str("rdma"),
+            TransportType::Fc => f.write_str("fc"),
+            TransportType::Loop => f.write_str("loop"),
+        }
+    }
+}
+
+impl TransportType {
+    /// Return the name of the kernel module that provides this transport.
+    pub fn module_name(&self) -> &'static str {
+        match self {
+            TransportType::Tcp => "nvmet-tcp",
+            TransportType::Rdma => "nvmet-rdma",
+            TransportType::Fc => "nvmet-fc",
+            TransportType::Loop => "nvme-loop",
+        }
+    }
+}
+
+/// Return whether the kernel module that provides the transport `t` is loaded.
+pub fn transport_available(t: TransportType) -> bool {
+    let module = t.module_name().replace('-', "_");
+    std::path::Path::new(SYSFS_MODULE_DIR).join(module).exists()
+}
+
+/// Return an error naming the missing module if the transport `t` is not available. A port
+/// using an unavailable transport can be created but will never serve connections.
+pub fn require_transport(t: TransportType) -> std::io::Result<()> {
+    if !transport_available(t) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("the {} module is not loaded, try `modprobe {}`", t.module_name(), t.module_name())
+        ));
+    }
+    Ok(())
+}
+
+/// A description of a port to create with [`create_ports`]. Empty address fields are left unset.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PortSpec {
+    pub id: u32,
+    pub transport: TransportType,
+    pub adrfam: String,
+    pub traddr: String,
+    pub trsvcid: String,
+}
+
+impl PortSpec {
+    /// Write the address of this spec to `port`, transport type first.
+    fn configure(&self, port: &mut Port) -> std::io::Result<()> {
+        port.set_addr_trtype(&self.transport.to_string())?;
+        if !self.adrfam.is_empty() {
+            port.set_addr_adrfam(&self.adrfam)?;
+        }
+        if !self.traddr.is_empty() {
+            port.set_addr_traddr(&self.traddr)?;
+        }
+        if !self.trsvcid.is_empty() {
+            port.set_addr_trsvcid(&self.trsvcid)?;
+        }
+        Ok(())
+    }
+}
+
+/// Create and configure a port for each spec. Return an error if any of the ports already
+/// exists. If any port cannot be created, every port created by this call is deleted again.
+pub fn create_ports(specs: &[PortSpec]) -> std::io::Result<Vec<Port>> {
+    let mut ports = Vec::new();
+    for spec in specs {
+        let mut port = Port::open(spec.id);
+        let res = std::fs::create_dir(port.path()).and_then(|_| {
+            ports.push(Port::open(spec.id));
+            spec.configure(&mut port)
+        });
+        if let Err(err) = res {
+            for port in &ports {
+                let _ = Port::delete(port.id());
+            }
+            return Err(err);
+        }
+    }
+    Ok(ports)
+}
+
+/// Remove all nvmet configuration. Port links and referrals are removed before ports, namespaces
+/// are disabled and removed before their subsystems, and hosts are removed last. Failures are
+/// skipped so that as much as possible is removed, and reported together at the end.
+pub fn teardown_all() -> std::io::Result<()> {
+    let mut errors = Vec::new();
+    for port in Port::list_all()? {
+        errors.extend(port.clear().err());
+        errors.extend(std::fs::remove_dir(port.path()).err());
+    }
+    for subsys in Subsystem::list_all()? {
+        errors.extend(subsys.clear().err());
+        errors.extend(std::fs::remove_dir(subsys.path()).err());
+    }
+    for host in Host::list_all()? {
+        errors.extend(std::fs::remove_dir(host.path()).err());
+    }
+    aggregate("teardown", errors)
+}
+
+/// Return a namespace of any subsystem whose device_path is `device_path`, if any.
+pub fn namespace_for_device(device_path: &str) -> std::io::Result<Option<Namespace>> {
+    for subsys in Subsystem::list_all()? {
+        if let Some(ns) = subsys.namespace_for_device(device_path)? {
+            return Ok(Some(ns));
+        }
+    }
+    Ok(None)
+}
+
+/// Return the parameters of the loaded nvmet modules keyed by `module.parameter`, e.g.
+/// `nvmet_tcp.so_priority`. Parameters that are not readable are skipped. Return a `NotFound`
+/// error if the nvmet module is not loaded.
+pub fn module_params() -> std::io::Result<std::collections::HashMap<String, String>> {
+    let module_dir = std::path::Path::new(SYSFS_MODULE_DIR);
+    if !module_dir.join("nvmet").try_exists()? {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound, "the nvmet module is not loaded"));
+    }
+    let mut params = std::collections::HashMap::new();
+    for module in std::fs::read_dir(module_dir)? {
+        let module = module?;
+        let module_name = module.file_name().to_string_lossy().into_owned();
+        if !module_name.starts_with("nvmet") {
+            continue;
+        }
+        let param_paths = match std::fs::read_dir(module.path().join("parameters")) {
+            Ok(param_paths) => param_paths,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+        for param in param_paths {
+            let param = param?;
+            if let Ok(value) = std::fs::read_to_string(param.path()) {
+                let key = format!("{}.{}", module_name, param.file_name().to_string_lossy());
+                params.insert(key, value.trim_end_matches('\n').to_string());
+            }
+        }
+    }
+    Ok(params)
+}
+
+/// Return the subsystems that are not linked to any port.
+pub fn unexported_subsystems() -> std::io::Result<Vec<Subsystem>> {
+    let mut subsystems = Vec::new();
+    for subsys in Subsystem::list_all()? {
+        if subsys.ports()?.is_empty() {
+            subsystems.push(subsys);
+        }
+    }
+    Ok(subsystems)
+}
+
+/// Return the NVMe UUID nqn `nqn.2014-08.org.nvmexpress:uuid:<uuid>` for the given uuid. Return
+/// an error if the uuid is not in the hyphenated form.
+pub fn uuid_nqn(uuid: &str) -> std::io::Result<String> {
+    if parse_uuid(uuid).is_none() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{:?} is not a valid UUID", uuid)
+        ));
+    }
+    Ok(format!("nqn.2014-08.org.nvmexpress:uuid:{}", uuid.to_ascii_lowercase()))
+}
+
+/// The entries a scan found, together with the path and error of every entry it had to skip.
+#[derive(Debug, Default)]
+pub struct Scan<T> {
+    pub items: Vec<T>,
+    pub skipped: Vec<(std::path::PathBuf, std::io::Error)>,
+}
+
+/// Return every namespace, across all subsystems, assigned to the ANA group `grpid`. Namespaces
+/// whose ana_grpid cannot be read are returned as skipped.
+pub fn namespaces_in_ana_group(grpid: u32) -> std::io::Result<Scan<(Subsystem, Namespace)>> {
+    let mut scan = Scan { items: Vec::new(), skipped: Vec::new() };
+    for subsys in Subsystem::list_all()? {
+        for ns in subsys.namespaces()? {
+            match ns.ana_grpid() {
+                Ok(ns_grpid) if ns_grpid == grpid => scan.items.push((Subsystem::open(subsys.nqn()), ns)),
+                Ok(_) => {}
+                Err(err) => scan.skipped.push((ns.path().to_path_buf(), err)),
+            }
+        }
+    }
+    Ok(scan)
+}
+
+/// The defaults that newly created ports and namespaces inherit, as returned by [`defaults`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Defaults {
+    /// The inline data size in bytes of tcp ports that do not set param_inline_data_size.
+    pub tcp_inline_data_size: u32,
+    /// The inline data size in bytes of rdma ports that do not set param_inline_data_size.
+    pub rdma_inline_data_size: u32,
+    /// Whether new namespaces use buffered I/O through the page cache. nvmet has no module
+    /// parameter for this, and always creates namespaces with buffered_io off.
+    pub buffered_io: bool,
+    /// The parameters of the loaded nvmet modules, or empty if nvmet is not loaded.
+    pub module_params: std::collections::HashMap<String, String>,
+}
+
+/// Return the defaults that newly created ports and namespaces inherit. The inline data sizes
+/// are the kernel transport defaults of four pages for tcp and one page for rdma, using the page
+/// size of the running kernel.
+pub fn defaults() -> std::io::Result<Defaults> {
+    let page_size = page_size()?;
+    let module_params = match module_params() {
+        Ok(params) => params,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => std::collections::HashMap::new(),
+        Err(err) => return Err(err),
+    };
+    Ok(Defaults {
+        tcp_inline_data_size: 4 * page_size,
+        rdma_inline_data_size: page_size,
+        buffered_io: false,
+        module_params,
+    })
+}
+
+/// Return every ANA group of every port with its state. Group directories that are not a valid
+/// group id or whose ana_state cannot be read are skipped.
+pub fn all_ana_groups() -> std::io::Result<Vec<(Port, u32, AnaState)>> {
+    let mut groups = Vec::new();
+    for port in Port::list_all()? {
+        for group in port.ana_groups()? {
+            if let Ok(state) = group.state() {
+                groups.push((Port::open(port.id()), group.grpid(), state));
+            }
+        }
+    }
+    Ok(groups)
+}
+
+/// Return the nvmet directory of the mounted configfs, found by reading `/proc/mounts`. Return a
+/// `NotFound` error if configfs is not mounted.
+pub fn detect_configfs_root() -> std::io::Result<std::path::PathBuf> {
+    let mounts = std::fs::read_to_string("/proc/mounts")?;
+    for line in mounts.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() >= 3 && fields[2] == "configfs" {
+            return Ok(std::path::PathBuf::from(unescape_mount_path(fields[1])).join("nvmet"));
+        }
+    }
+    Err(std::io::Error::new(std::io::ErrorKind::NotFound, "configfs is not mounted"))
+}
+
+/// Decode the octal escapes, e.g. `\040` for a space, used in `/proc/mounts` paths.
+fn unescape_mount_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 3 < bytes.len() {
+            let digits = std::str::from_utf8(&bytes[i + 1..i + 4]).unwrap_or("");
+            if let Ok(byte) = u8::from_str_radix(digits, 8) {
+                out.push(byte);
+                i += 4;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// The hosts an [`AccessEntry`] applies to.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AccessHost {
+    /// Any host, through subsystems with attr_allow_any_host set.
+    Any,
+    /// The host with the given nqn.
+    Nqn(String),
+}
+
+/// The namespaces a host can reach, as returned by [`effective_access`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccessEntry {
+    pub host: AccessHost,
+    pub namespaces: Vec<NamespaceId>,
+}
+
+/// Return, for each host, the namespaces it can reach. A host can reach the enabled namespaces of
+/// every subsystem that has a working link to a port and either allows any host or lists the
+/// host in allowed_hosts. Namespaces of subsystems that allow any host are reported under
+/// [`AccessHost::Any`] rather than repeated for every host.
+pub fn effective_access() -> std::io::Result<Vec<AccessEntry>> {
+    let ports: Vec<Port> = Port::list_all()?.collect();
+    let mut access: std::collections::BTreeMap<AccessHost, Vec<NamespaceId>> = std::collections::BTreeMap::new();
+    for subsys in Subsystem::list_all()? {
+        let mut exported = false;
+        for port in &ports {
+            if port.has_working_subsystem(&subsys)? {
+                exported = true;
+                break;
+            }
+        }
+        if !exported {
+            continue;
+        }
+        let mut namespaces: Vec<NamespaceId> = Vec::new();
+        for ns in subsys.namespaces()? {
+            let Some(nsid) = ns.nsid() else { continue };
+            if ns.enable()? {
+                namespaces.push((subsys.nqn().to_string(), nsid));
+            }
+        }
+        if subsys.attr_allow_any_host()? {
+            access.entry(AccessHost::Any).or_default().extend(namespaces);
+        } else {
+            for host in subsys.allowed_hosts()? {
+                access.entry(AccessHost::Nqn(host.nqn().to_string())).or_default().extend(namespaces.iter().cloned());
+            }
+        }
+    }
+    Ok(access.into_iter().map(|(host, namespaces)| AccessEntry { host, namespaces }).collect())
+}
+
+/// Export the device at `device_path` as namespace 1 of the subsystem `nqn` on `port`. The
+/// subsystem is created if it does not exist, the namespace gets a freshly generated
+/// device_uuid and is enabled, and the subsystem is linked to the port. Host access is left
+/// unchanged. If any step fails, everything created by this call is removed again.
+pub fn export_device(nqn: &str, device_path: &str, port: &Port) -> std::io::Result<(Subsystem, Namespace)> {
+    let created = !Subsystem::exists(nqn)?;
+    let subsys = if created { Subsystem::new(nqn)? } else { Subsystem::open(nqn) };
+    let mut ns = None;
+    let mut linked = false;
+    let res = subsys.create_namespace(1).and_then(|created_ns| {
+        let ns = ns.insert(created_ns);
+        ns.set_device_path(device_path)?;
+        ns.set_device_uuid(&generate_uuid()?)?;
+        ns.set_enable(true)?;
+        if !port.has_subsystem(&subsys)? {
+            port.add_subsystem(&subsys)?;
+            linked = true;
+        }
+        Ok(())
+    });
+    if let Err(err) = res {
+        if linked {
+            let _ = port.remove_subsystem(subsys.nqn());
+        }
+        if let Some(mut ns) = ns {
+            let _ = ns.set_enable(false);
+            let _ = std::fs::remove_dir(ns.path());
+        }
+        if created {
+            let _ = std::fs::remove_dir(subsys.path());
+        }
+        return Err(err);
+    }
+    Ok((subsys, ns.unwrap()))
+}
+
+/// Export the device at `device_path` over the loop transport for local testing. A subsystem
+/// with a freshly generated uuid nqn and allow_any_host set is created with the device as
+/// namespace 1, and is linked to a new loop port with the smallest unused id. If any step fails,
+/// everything created by this call is removed again.
+pub fn export_loopback(device_path: &str) -> std::io::Result<(Subsystem, Port)> {
+    let mut subsys = Subsystem::new(uuid_nqn(&generate_uuid()?)?)?;
+    let mut port = None;
+    let res = subsys.set_attr_allow_any_host(true).and_then(|_| {
+        let mut used: std::collections::HashSet<u32> = Port::list_all()?.map(|port| port.id()).collect();
+        let mut id = 1;
+        let port = loop {
+            while used.contains(&id) {
+                id += 1;
+            }
+            match std::fs::create_dir(Port::open(id).path()) {
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                    used.insert(id);
+                }
+                Err(err) => return Err(err),
+                Ok(()) => break port.insert(Port::open(id)),
+            }
+        };
+        port.set_addr_trtype(&TransportType::Loop.to_string())?;
+        export_device(subsys.nqn(), device_path, port)?;
+        Ok(())
+    });
+    if let Err(err) = res {
+        if let Some(port) = port {
+            let _ = std::fs::remove_dir(port.path());
+        }
+        let _ = std::fs::remove_dir(subsys.path());
+        return Err(err);
+    }
+    Ok((subsys, port.unwrap()))
+}
+
+/// The identifiers of all configured objects, as returned by [`inventory`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Inventory {
+    /// The nqns of all subsystems.
+    pub subsystems: Vec<String>,
+    /// The ids of all ports.
+    pub ports: Vec<u32>,
+    /// The nqns of all hosts.
+    pub hosts: Vec<String>,
+    /// The number of namespaces across all subsystems.
+    pub namespace_count: usize,
+}
+
+/// Return the identifiers of all subsystems, ports, and hosts and the total number of
+/// namespaces. No attributes are read.
+pub fn inventory() -> std::io::Result<Inventory> {
+    let mut inventory = Inventory::default();
+    for subsys in Subsystem::list_all()? {
+        inventory.namespace_count += subsys.namespaces()?.count();
+        inventory.subsystems.push(subsys.nqn().to_string());
+    }
+    inventory.ports = Port::list_all()?.map(|port| port.id()).collect();
+    inventory.hosts = Host::list_all()?.map(|host| host.nqn().to_string()).collect();
+    inventory.subsystems.sort();
+    inventory.ports.sort();
+    inventory.hosts.sort();
+    Ok(inventory)
+}
+
+/// Return the subsystem nqn, nsid, and device_path of every namespace whose device_path is set
+/// but no longer exists.
+pub fn find_missing_backing_devices() -> std::io::Result<Vec<(String, u32, String)>> {
+    let mut missing = Vec::new();
+    for subsys in Subsystem::list_all()? {
+        for ns in subsys.namespaces()? {
+            let Some(nsid) = ns.nsid() else { continue };
+            if let Some(device_path) = ns.device_path()? {
+                if !std::path::Path::new(&device_path).try_exists()? {
+                    missing.push((subsys.nqn().to_string(), nsid, device_path));
+                }
+            }
+        }
+    }
+    Ok(missing)
+}
+
+/// Return the NGUID derived from a hyphenated uuid: the 16 bytes of the uuid hex encoded as 32
+/// lowercase hex digits, so that the uuid can be recovered from the NGUID. Return an error if
+/// the uuid is malformed.
+pub fn nguid_from_uuid(uuid: &str) -> std::io::Result<String> {
+    let bytes = parse_uuid(uuid).ok_or_else(|| std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("{:?} is not a valid UUID", uuid)
+    ))?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Return whether the kernel supports ANA, by checking for the `ana_groups` directory of an
+/// existing port. Return None if there is no port to check.
+pub fn ana_supported() -> std::io::Result<Option<bool>> {
+    match Port::list_all()?.next() {
+        Some(port) => Ok(Some(port.path().join("ana_groups").try_exists()?)),
+        None => Ok(None),
+    }
+}
+
+/// How serious a [`LintFinding`] is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LintSeverity {
+    /// The configuration is valid but probably not what was intended.
+    Info,
+    /// Hosts may not get the access the configuration appears to grant.
+    Warning,
+    /// Hosts may see broken or conflicting namespaces.
+    Error,
+}
+
+/// A problem in the configuration, as returned by [`lint`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LintFinding {
+    pub severity: LintSeverity,
+    pub description: String,
+}
+
+/// Check the whole configuration for subsystems not linked to any port, device_uuids used by
+/// more than one namespace, missing backing devices, subsystems that allow any host while
+/// having allowed hosts, and linked subsystems no host can connect to.
+pub fn lint() -> std::io::Result<Vec<LintFinding>> {
+    let mut findings = Vec::new();
+    let mut finding = |severity, description| findings.push(LintFinding { severity, description });
+    for subsys in unexported_subsystems()? {
+        finding(LintSeverity::Info, format!("subsystem {} is not linked to any port", subsys.nqn()));
+    }
+    for (uuid, users) in find_duplicate_uuids()? {
+        let users: Vec<String> = users.iter().map(|(nqn, nsid)| format!("{}/{}", nqn, nsid)).collect();
+        finding(LintSeverity::Error, format!("device_uuid {} is used by namespaces {}", uuid, users.join(", ")));
+    }
+    for (nqn, nsid, device_path) in find_missing_backing_devices()? {
+        finding(LintSeverity::Error, format!("namespace {}/{} is backed by missing device {}", nqn, nsid, device_path));
+    }
+    for subsys in Subsystem::list_all()? {
+        let count = subsys.allowed_hosts()?.count();
+        if count > 0 && subsys.attr_allow_any_host()? {
+            finding(
+                LintSeverity::Warning,
+                format!("subsystem {} allows any host, so its {} allowed hosts do not restrict access", subsys.nqn(), count)
+            );
+        }
+        if subsys.accessibility()? == Accessibility::NoHosts {
+            finding(
+                LintSeverity::Warning,
+                format!("subsystem {} is linked to a port but no host is allowed to connect", subsys.nqn())
+            );
+        }
+    }
+    Ok(findings)
+}
+
+/// Return the hosts that are not in the allowed_hosts of any subsystem. Such hosts grant no
+/// access and can be deleted.
+pub fn unused_hosts() -> std::io::Result<Vec<Host>> {
+    let mut used = std::collections::HashSet::new();
+    for subsys in Subsystem::list_all()? {
+        for host in subsys.allowed_hosts()? {
+            used.insert(host.nqn().to_string());
+        }
+    }
+    Ok(Host::list_all()?.filter(|host| !used.contains(host.nqn())).collect())
+}
+
+/// Set the ANA state of group `grpid` on every port that has the group, e.g. to mark it
+/// inaccessible during a failover. Every port is attempted even if an earlier one fails, and the
+/// result of each write is returned together with its port.
+pub fn set_ana_state_everywhere(grpid: u32, state: AnaState) -> std::io::Result<Vec<(Port, std::io::Result<()>)>> {
+    let mut results = Vec::new();
+    for port in Port::list_all()? {
+        let mut group = port.ana_group(grpid);
+        if group.path().try_exists()? {
+            let res = group.set_state(&state);
+            results.push((port, res));
+        }
+    }
+    Ok(results)
+}
+
+/// The whole configuration as a nested tree of ports, their linked subsystems, and the
+/// subsystems' namespaces, as returned by [`tree`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NvmetTree {
+    pub ports: Vec<PortNode>,
+    /// The subsystems that are not linked to any port.
+    pub unlinked_subsystems: Vec<SubsystemNode>,
+    /// The nqns of all hosts.
+    pub hosts: Vec<String>,
+}
+
+/// A port in an [`NvmetTree`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PortNode {
+    pub id: u32,
+    pub transport: TransportInfo,
+    pub subsystems: Vec<SubsystemNode>,
+}
+
+/// A subsystem in an [`NvmetTree`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SubsystemNode {
+    pub nqn: String,
+    pub allow_any_host: bool,
+    /// The nqns of the hosts in allowed_hosts.
+    pub allowed_hosts: Vec<String>,
+    pub namespaces: Vec<NamespaceNode>,
+}
+
+/// A namespace in an [`NvmetTree`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NamespaceNode {
+    pub nsid: u32,
+    pub device_path: Option<String>,
+    pub enabled: bool,
+}
+
+impl SubsystemNode {
+    fn read(subsys: &Subsystem) -> std::io::Result<SubsystemNode> {
+        let mut allowed_hosts: Vec<String> = subsys.allowed_hosts()?.map(|host| host.nqn().to_string()).collect();
+        allowed_hosts.sort();
+        let mut namespaces = Vec::new();
+        for ns in subsys.namespaces()? {
+            let Some(nsid) = ns.nsid() else { continue };
+            namespaces.push(NamespaceNode { nsid, device_path: ns.device_path()?, enabled: ns.enable()? });
+        }
+        namespaces.sort_by_key(|ns| ns.nsid);
+        Ok(SubsystemNode {
+            nqn: subsys.nqn().to_string(),
+            allow_any_host: subsys.attr_allow_any_host()?,
+            allowed_hosts,
+            namespaces,
+        })
+    }
+}
+
+/// Return the whole configuration as a tree. Unlike [`inventory`], this models which subsystems
+/// are linked to which ports; a subsystem linked to several ports appears under each of them.
+/// Dangling subsystem links are skipped. Ports, subsystems, namespaces, and hosts are sorted by
+/// id, nqn, and nsid.
+pub fn tree() -> std::io::Result<NvmetTree> {
+    let mut tree = NvmetTree::default();
+    let mut linked = std::collections::HashSet::new();
+    for port in Port::list_all()? {
+        let mut subsystems = Vec::new();
+        for subsys in port.subsystems()? {
+            if !port.has_working_subsystem(&subsys)? {
+                continue;
+            }
+            linked.insert(subsys.nqn().to_string());
+            subsystems.push(SubsystemNode::read(&subsys)?);
+        }
+        subsystems.sort_by(|a, b| a.nqn.cmp(&b.nqn));
+        tree.ports.push(PortNode { id: port.id(), transport: port.transport_info()?, subsystems });
+    }
+    tree.ports.sort_by_key(|port| port.id);
+    for subsys in Subsystem::list_all()? {
+        if !linked.contains(subsys.nqn()) {
+            tree.unlinked_subsystems.push(SubsystemNode::read(&subsys)?);
+        }
+    }
+    tree.unlinked_subsystems.sort_by(|a, b| a.nqn.cmp(&b.nqn));
+    tree.hosts = Host::list_all()?.map(|host| host.nqn().to_string()).collect();
+    tree.hosts.sort();
+    Ok(tree)
+}
+
+/// Set whether the discovery controller sends hosts an asynchronous event when the discovery
+/// log changes, prompting them to re-run discovery. The discovery subsystem is not in configfs,
+/// so its settings live at the nvmet root next to `discovery_nqn`; this is only available on
+/// kernels that expose a `discovery_aen` attribute there.
+pub fn set_discovery_aen(value: bool) -> std::io::Result<()> {
+    write(probe(std::path::Path::new(CONFIGFS_DIR).join("discovery_aen"))?, if value { "1" } else { "0" })
+}
+
+pub fn discovery_aen() -> std::result::Result<bool, ReadError<u8>> {
+    read(probe(std::path::Path::new(CONFIGFS_DIR).join("discovery_aen")).map_err(ReadError::Io)?).map(|v: u8| v == 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: &str = "4c1e3e1a-9b2d-4f6a-8c7e-0123456789ab";
+    const UUID_BYTES: [u8; 16] = [
+        0x4c, 0x1e, 0x3e, 0x1a, 0x9b, 0x2d, 0x4f, 0x6a, 0x8c, 0x7e, 0x01, 0x23, 0x45, 0x67, 0x89, 0xab,
+    ];
+
+    /// Return a fresh scratch directory holding the given attribute files.
+    fn scratch_dir(name: &str, attrs: &[(&str, &str)]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("nvmet-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        for (attr, value) in attrs {
+            std::fs::write(path.join(attr), format!("{}\n", value)).unwrap();
+        }
+        path
+    }
+
+    /// Return a Namespace backed by a fresh scratch directory holding the given attribute files.
+    fn scratch_namespace(name: &str, attrs: &[(&str, &str)]) -> Namespace {
+        Namespace { path: scratch_dir(name, attrs) }
+    }
+
+    /// Return a Subsystem backed by a fresh scratch directory holding the given attribute files.
+    fn scratch_subsystem(name: &str, attrs: &[(&str, &str)]) -> Subsystem {
+        Subsystem { nqn: std::ffi::OsString::from(name), path: scratch_dir(name, attrs) }
+    }
+
+    #[test]
+    fn parse_hex_id_accepts_32_hex_digits() {
+        assert_eq!(parse_hex_id("4c1e3e1a9b2d4f6a8c7e0123456789ab"), Some(UUID_BYTES));
+        assert_eq!(parse_hex_id("4C1E3E1A9B2D4F6A8C7E0123456789AB"), Some(UUID_BYTES));
+    }
+
+    #[test]
+    fn parse_hex_id_rejects_malformed_input() {
+        assert_eq!(parse_hex_id(""), None);
+        assert_eq!(parse_hex_id("4c1e3e1a9b2d4f6a8c7e0123456789a"), None);
+        assert_eq!(parse_hex_id("4c1e3e1a9b2d4f6a8c7e0123456789abc"), None);
+        assert_eq!(parse_hex_id("4c1e3e1a9b2d4f6a8c7e0123456789ag"), None);
+        assert_eq!(parse_hex_id("+c1e3e1a9b2d4f6a8c7e0123456789ab"), None);
+    }
+
+    #[test]
+    fn parse_uuid_requires_hyphenated_form() {
+        assert_eq!(parse_uuid(UUID), Some(UUID_BYTES));
+        assert_eq!(parse_uuid("4c1e3e1a9b2d4f6a8c7e0123456789ab"), None);
+        assert_eq!(parse_uuid("4c1e3e1a-9b2d-4f6a-8c7e0123-456789ab"), None);
+        assert_eq!(parse_uuid("4c1e3e1a-9b2d-4f6a-8c7e-0123456789ag"), None);
+    }
+
+    #[test]
+    fn parse_nguid_accepts_plain_and_hyphenated_form() {
+        assert_eq!(parse_nguid("4c1e3e1a9b2d4f6a8c7e0123456789ab"), Some(UUID_BYTES));
+        assert_eq!(parse_nguid(UUID), Some(UUID_BYTES));
+        assert_eq!(parse_nguid("4c1e3e1a-9b2d"), None);
+    }
+
+    #[test]
+    fn validate_identifiers_accepts_valid_identifiers() {
+        let ns = scratch_namespace("valid-ids", &[("device_uuid", UUID), ("device_nguid", UUID)]);
+        assert!(ns.validate_identifiers().is_ok());
+        std::fs::remove_dir_all(ns.path()).unwrap();
+    }
+
+    #[test]
+    fn validate_identifiers_rejects_all_zero_identifiers() {
+        let zero_uuid = "00000000-0000-0000-0000-000000000000";
+        let ns = scratch_namespace("zero-uuid", &[("device_uuid", zero_uuid), ("device_nguid", UUID)]);
+        assert_eq!(ns.validate_identifiers().unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        std::fs::remove_dir_all(ns.path()).unwrap();
+
+        let ns = scratch_namespace("zero-nguid", &[("device_uuid", UUID), ("device_nguid", zero_uuid)]);
+        assert_eq!(ns.validate_identifiers().unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        std::fs::remove_dir_all(ns.path()).unwrap();
+    }
+
+    #[test]
+    fn validate_identifiers_rejects_malformed_identifiers() {
+        let ns = scratch_namespace("bad-nguid", &[("device_uuid", UUID), ("device_nguid", "not-an-nguid")]);
+        assert_eq!(ns.validate_identifiers().unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        std::fs::remove_dir_all(ns.path()).unwrap();
+    }
+
+    #[test]
+    fn unescape_mount_path_decodes_octal_escapes() {
+        assert_eq!(unescape_mount_path("/sys/kernel/config"), "/sys/kernel/config");
+        assert_eq!(unescape_mount_path("/mnt/my\\040config"), "/mnt/my config");
+        assert_eq!(unescape_mount_path("/mnt/tab\\011and\\134slash"), "/mnt/tab\tand\\slash");
+        assert_eq!(unescape_mount_path("\\040"), " ");
+    }
+
+    #[test]
+    fn unescape_mount_path_keeps_incomplete_escapes() {
+        assert_eq!(unescape_mount_path("/mnt/a\\04"), "/mnt/a\\04");
+        assert_eq!(unescape_mount_path("/mnt/a\\xyz"), "/mnt/a\\xyz");
+        assert_eq!(unescape_mount_path("/mnt/\u{e9}\\040b"), "/mnt/\u{e9} b");
+    }
+
+    #[test]
+    fn command_support_parses_hex_and_decimal() {
+        let flags: CommandSupport = "0x000c".parse().unwrap();
+        assert_eq!(flags, CommandSupport::DATASET_MANAGEMENT | CommandSupport::WRITE_ZEROES);
+        assert_eq!("12".parse::<CommandSupport>().unwrap(), flags);
+        assert!(flags.contains(CommandSupport::WRITE_ZEROES));
+        assert!(!flags.contains(CommandSupport::COMPARE));
+        assert!(!flags.contains(CommandSupport::WRITE_ZEROES | CommandSupport::COMPARE));
+        assert!("0xg".parse::<CommandSupport>().is_err());
+        assert!("0x10000".parse::<CommandSupport>().is_err());
+    }
+
+    #[test]
+    fn command_support_display_round_trips() {
+        let flags = CommandSupport::COMPARE | CommandSupport::COPY;
+        assert_eq!(flags.to_string(), "0x0101");
+        assert_eq!(flags.to_string().parse::<CommandSupport>().unwrap(), flags);
+        assert_eq!(CommandSupport::from_bits(0x8000).bits(), 0x8000);
+    }
+
+    #[test]
+    fn ana_state_round_trips() {
+        let states = [
+            AnaState::Optimized,
+            AnaState::NonOptimized,
+            AnaState::Inaccessible,
+            AnaState::PersistentLoss,
+            AnaState::Change,
+            AnaState::Other("future-state".to_string()),
+        ];
+        for state in states {
+            assert_eq!(state.to_string().parse::<AnaState>().unwrap(), state);
+        }
+        assert_eq!("non-optimized".parse::<AnaState>().unwrap(), AnaState::NonOptimized);
+    }
+
+    #[test]
+    fn transport_requirements_round_trip() {
+        let treqs = [
+            TransportRequirements::NotSpecified,
+            TransportRequirements::Required,
+            TransportRequirements::NotRequired,
+            TransportRequirements::Other("optional".to_string()),
+        ];
+        for treq in treqs {
+            assert_eq!(treq.to_string().parse::<TransportRequirements>().unwrap(), treq);
+        }
+        assert_eq!("not required".parse::<TransportRequirements>().unwrap(), TransportRequirements::NotRequired);
+    }
+
+    #[test]
+    fn check_cntlid_rejects_reserved_ids() {
+        assert!(check_cntlid(CNTLID_MIN).is_ok());
+        assert!(check_cntlid(CNTLID_MAX).is_ok());
+        for reserved in [0x0000, 0xFFF0, 0xFFFF] {
+            assert_eq!(check_cntlid(reserved).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn cntlid_setters_reject_reserved_ids_before_writing() {
+        let mut subsys = Subsystem::open("nqn.2014-08.org.example:test-reserved-cntlid");
+        assert_eq!(subsys.set_attr_cntlid_min(0).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(subsys.set_attr_cntlid_max(0xFFFF).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn create_namespace_rejects_reserved_nsids() {
+        let subsys = Subsystem::open("nqn.2014-08.org.example:test-reserved-nsid");
+        for reserved in [0, 0xFFFFFFFF] {
+            assert_eq!(subsys.create_namespace(reserved).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn nguid_from_uuid_encodes_uuid_bytes() {
+        let nguid = nguid_from_uuid(UUID).unwrap();
+        assert_eq!(nguid, "4c1e3e1a9b2d4f6a8c7e0123456789ab");
+        assert_eq!(parse_nguid(&nguid), parse_uuid(UUID));
+        assert_eq!(nguid_from_uuid(&UUID.to_ascii_uppercase()).unwrap(), nguid);
+    }
+
+    #[test]
+    fn nguid_from_uuid_rejects_malformed_uuids() {
+        for uuid in ["", "4c1e3e1a9b2d4f6a8c7e0123456789ab", "4c1e3e1a-9b2d-4f6a-8c7e-0123456789ag"] {
+            assert_eq!(nguid_from_uuid(uuid).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn set_limits_writes_nothing_without_attr_qid_max() {
+        let mut subsys = scratch_subsystem("limits", &[("attr_cntlid_min", "1"), ("attr_cntlid_max", "65519")]);
+        let limits = ControllerLimits { cntlid_min: 5, cntlid_max: 10, qid_max: Some(8) };
+        assert_eq!(subsys.set_limits(&limits).unwrap_err().kind(), std::io::ErrorKind::Unsupported);
+        assert_eq!(subsys.cntlid_range().unwrap(), 1..=65519);
+        std::fs::remove_dir_all(subsys.path()).unwrap();
+    }
+
+    #[test]
+    fn set_identity_writes_nothing_without_optional_attributes() {
+        let mut subsys = scratch_subsystem("identity", &[("attr_model", "Linux"), ("attr_serial", "abc")]);
+        let identity = ControllerIdentity {
+            model: Some("model".to_string()),
+            serial: Some("serial".to_string()),
+            firmware: Some("1.0".to_string()),
+            ..ControllerIdentity::default()
+        };
+        assert_eq!(subsys.set_identity(&identity).unwrap_err().kind(), std::io::ErrorKind::Unsupported);
+        assert_eq!(subsys.attr_model().unwrap(), "Linux");
+        assert_eq!(subsys.attr_serial().unwrap(), "abc");
+        std::fs::remove_dir_all(subsys.path()).unwrap();
+    }
+
+    #[test]
+    fn set_identity_rejects_overlong_fields() {
+        let mut subsys = scratch_subsystem("identity-len", &[("attr_model", "Linux"), ("attr_serial", "abc")]);
+        let identity = ControllerIdentity {
+            model: Some("m".to_string()),
+            serial: Some("s".repeat(21)),
+            ..ControllerIdentity::default()
+        };
+        assert_eq!(subsys.set_identity(&identity).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(subsys.attr_model().unwrap(), "Linux");
+        std::fs::remove_dir_all(subsys.path()).unwrap();
+    }
+
+    #[test]
+    fn subsystems_by_target_reports_dangling_links() {
+        let root = scratch_dir("port-links", &[]);
+        let target = root.join("nqn.target");
+        std::fs::create_dir_all(root.join("port").join("subsystems")).unwrap();
+        std::fs::create_dir(&target).unwrap();
+        let links = root.join("port").join("subsystems");
+        std::os::unix::fs::symlink(&target, links.join("nqn.renamed")).unwrap();
+        std::os::unix::fs::symlink(root.join("nqn.gone"), links.join("nqn.gone")).unwrap();
+
+        let port = Port { id: 1, path: root.join("port") };
+        let (subsystems, dangling) = port.subsystems_by_target().unwrap();
+        let nqns: Vec<&str> = subsystems.iter().map(|subsys| subsys.nqn()).collect();
+        assert_eq!(nqns, ["nqn.target"]);
+        assert_eq!(dangling, [("nqn.gone".to_string(), root.join("nqn.gone"))]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn probe_distinguishes_missing_object_from_missing_attribute() {
+        let dir = scratch_dir("probe", &[("present", "1")]);
+        assert_eq!(probe(dir.join("present")).unwrap(), dir.join("present"));
+        assert_eq!(probe(dir.join("absent")).unwrap_err().kind(), std::io::ErrorKind::Unsupported);
+        assert_eq!(probe(dir.join("gone").join("absent")).unwrap_err().kind(), std::io::ErrorKind::NotFound);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn is_shared_defaults_to_true_only_for_existing_namespaces() {
+        let ns = scratch_namespace("shared-default", &[]);
+        assert!(ns.is_shared().unwrap());
+        std::fs::write(ns.path().join("shared"), "0\n").unwrap();
+        assert!(!ns.is_shared().unwrap());
+        std::fs::remove_dir_all(ns.path()).unwrap();
+
+        let missing = Namespace { path: ns.path().join("namespaces").join("1") };
+        match missing.is_shared() {
+            Err(ReadError::Io(err)) => assert_eq!(err.kind(), std::io::ErrorKind::NotFound),
+            other => panic!("expected NotFound, got {:?}", other.map_err(|err| err.to_string())),
+        }
+    }
+
+    #[test]
+    fn read_opt_attr_distinguishes_missing_object_from_missing_attribute() {
+        let dir = scratch_dir("read-opt-attr", &[("empty", "")]);
+        assert_eq!(read_opt_attr(&dir, "empty").unwrap(), Some(String::new()));
+        assert_eq!(read_opt_attr(&dir, "absent").unwrap(), None);
+        assert_eq!(read_opt_attr(&dir.join("gone"), "absent").unwrap_err().kind(), std::io::ErrorKind::NotFound);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn max_namespaces_falls_back_only_for_existing_subsystems() {
+        let subsys = scratch_subsystem("max-ns", &[]);
+        assert_eq!(subsys.max_namespaces().unwrap(), 1024);
+        std::fs::write(subsys.path().join("attr_max_namespaces"), "32\n").unwrap();
+        assert_eq!(subsys.max_namespaces().unwrap(), 32);
+        std::fs::remove_dir_all(subsys.path()).unwrap();
+        assert_eq!(subsys.max_namespaces().unwrap_err().kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn page_size_is_a_power_of_two() {
+        let page_size = page_size().unwrap();
+        assert!(page_size >= 4096 && page_size.is_power_of_two());
+    }
+
+    #[test]
+    fn identify_controller_fields_propagates_missing_subsystem() {
+        let subsys = scratch_subsystem("identify-missing", &[]);
+        std::fs::remove_dir_all(subsys.path()).unwrap();
+        assert_eq!(subsys.identify_controller_fields().unwrap_err().kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn identify_controller_fields_defaults_unsupported_firmware() {
+        let subsys = scratch_subsystem("identify-defaults", &[("attr_model", ""), ("attr_serial", "abc")]);
+        let fields = subsys.identify_controller_fields().unwrap();
+        assert_eq!(fields.model, "Linux");
+        assert_eq!(fields.version, "1.3");
+        assert!(!fields.firmware.is_empty() && fields.firmware.len() <= 8);
+        std::fs::remove_dir_all(subsys.path()).unwrap();
+    }
+
+    #[test]
+    fn ana_groups_and_referrals_stay_under_their_port() {
+        let root = scratch_dir("port-children", &[]);
+        std::fs::create_dir_all(root.join("ana_groups").join("1")).unwrap();
+        std::fs::create_dir_all(root.join("referrals").join("r1")).unwrap();
+        let port = Port { id: 1, path: root.clone() };
+        let groups: Vec<std::path::PathBuf> = port.ana_groups().unwrap().map(|group| group.path()).collect();
+        assert_eq!(groups, [root.join("ana_groups").join("1")]);
+        let referrals: Vec<std::path::PathBuf> = port.referrals().unwrap().map(|referral| referral.path()).collect();
+        assert_eq!(referrals, [root.join("referrals").join("r1")]);
+        assert_eq!(port.referral("r2").path(), root.join("referrals").join("r2"));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn port_snapshot_restore_writes_through_the_given_port() {
+        let addr = [("addr_trtype", "tcp"), ("addr_adrfam", "ipv4"), ("addr_traddr", "10.0.0.1"), ("addr_trsvcid", "4420")];
+        let root = scratch_dir("restore", &addr);
+        std::fs::create_dir(root.join("subsystems")).unwrap();
+        std::fs::create_dir(root.join("referrals")).unwrap();
+        let referral = root.join("referrals").join("r1");
+        std::fs::create_dir(&referral).unwrap();
+        for (name, value) in [("addr_trtype", "tcp"), ("addr_adrfam", "ipv4"), ("addr_traddr", "10.0.0.2"), ("addr_trsvcid", "4420"), ("enable", "1")] {
+            std::fs::write(referral.join(name), format!("{}\n", value)).unwrap();
+        }
+        let mut port = Port { id: 1, path: root.clone() };
+        let snapshot = port.snapshot().unwrap();
+
+        std::fs::write(referral.join("addr_traddr"), "10.0.0.3\n").unwrap();
+        snapshot.restore(&mut port).unwrap();
+        assert_eq!(port.referral("r1").addr_traddr().unwrap(), "10.0.0.2");
+        assert_eq!(port.snapshot().unwrap(), snapshot);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn fc_address_round_trips() {
+        let mut port = Port { id: 1, path: scratch_dir("fc-address", &[]) };
+        port.set_fc_address(0x20000090fa000001, 0x10000090fa000001).unwrap();
+        assert_eq!(port.addr_traddr().unwrap(), "nn-0x20000090fa000001:pn-0x10000090fa000001");
+        assert_eq!(port.fc_address().unwrap(), Some((0x20000090fa000001, 0x10000090fa000001)));
+        std::fs::remove_dir_all(port.path()).unwrap();
+    }
+
+    #[test]
+    fn fc_address_rejects_malformed_names() {
+        let mut port = Port { id: 1, path: scratch_dir("fc-address-bad", &[]) };
+        for traddr in ["nn-0x+1:pn-0x2", "nn-0x:pn-0x2", "nn-0x12345678901234567:pn-0x2", "nn-0x1-pn-0x2", "192.168.0.1"] {
+            port.set_addr_traddr(traddr).unwrap();
+            assert_eq!(port.fc_address().unwrap(), None, "{}", traddr);
+        }
+        port.set_addr_traddr("nn-0x1:pn-0xAB").unwrap();
+        assert_eq!(port.fc_address().unwrap(), Some((0x1, 0xab)));
+        std::fs::remove_dir_all(port.path()).unwrap();
+    }
+
+    #[test]
+    fn io_stats_converts_sectors_to_bytes() {
+        let ns = scratch_namespace("io-stats", &[("stat", "3 0 8 0 5 0 16 0 0 0 0")]);
+        let stats = ns.io_stats().unwrap();
+        assert_eq!(stats, IoStats { read_ios: 3, read_bytes: 4096, write_ios: 5, write_bytes: 8192 });
+        std::fs::remove_dir_all(ns.path()).unwrap();
+    }
+
+    #[test]
+    fn io_stats_rejects_overflowing_sector_counts() {
+        let stat = format!("1 0 {} 0 1 0 1 0 0 0 0", u64::MAX / 4);
+        let ns = scratch_namespace("io-stats-overflow", &[("stat", &stat)]);
+        assert_eq!(ns.io_stats().unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        std::fs::remove_dir_all(ns.path()).unwrap();
     }
 }